@@ -0,0 +1,50 @@
+#!/bin/bash
+# Benchmark hyper vs reqwest da seção "Backend HTTP" do README
+# Uso: scripts/bench/run.sh BACKEND TOTAL CONC [RODADAS]
+# Ex.: scripts/bench/run.sh hyper 20000 64 3
+#
+# Sobe dois processadores falsos (8101/8102) e o p99 em release (9990),
+# aquece com 2.000 requisições e mede TOTAL por rodada. Ao fim de cada
+# rodada mostra a latência da chamada ao processador A (/metrics)
+set -euo pipefail
+
+BACKEND=${1:?backend: reqwest | hyper}
+TOTAL=${2:?total de requisições}
+CONC=${3:?conexões simultâneas}
+ROUNDS=${4:-3}
+
+HERE=$(cd "$(dirname "$0")" && pwd)
+REPO=$(cd "$HERE/../.." && pwd)
+BIN=$HERE/target/release
+
+cargo build --release --manifest-path "$REPO/Cargo.toml"
+cargo build --release --manifest-path "$HERE/Cargo.toml"
+
+cleanup() {
+    kill "${PIDS[@]}" 2>/dev/null || true
+    wait 2>/dev/null || true
+}
+trap cleanup EXIT
+
+for round in $(seq "$ROUNDS"); do
+    PIDS=()
+    "$BIN/upstream" 8101 & PIDS+=($!)
+    "$BIN/upstream" 8102 & PIDS+=($!)
+    PORT=9990 \
+    UPSTREAM_A_URL=http://127.0.0.1:8101 \
+    UPSTREAM_B_URL=http://127.0.0.1:8102 \
+    UPSTREAM_PAY_PATH=/payments \
+    UPSTREAM_BACKEND=$BACKEND \
+    RUST_LOG=warn \
+        "$REPO/target/release/p99" > /dev/null 2>&1 & PIDS+=($!)
+    sleep 1
+
+    "$BIN/load" http://127.0.0.1:9990/payments 2000 "$CONC" > /dev/null
+    echo "round $round ($BACKEND, $CONC conexões): $("$BIN/load" http://127.0.0.1:9990/payments "$TOTAL" "$CONC")"
+    curl -s localhost:9990/metrics \
+        | grep '^upstream_latency_ms{' \
+        | grep 'upstream="A"' \
+        | grep -E 'quantile="(0.5|0.99)"'
+
+    cleanup
+done
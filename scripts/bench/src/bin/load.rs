@@ -0,0 +1,92 @@
+/// Gerador de carga: TOTAL `POST /payments` com CONC conexões em paralelo
+/// Uso: load URL TOTAL CONC
+/// Imprime sucessos, erros, RPS e percentis da latência ponta a ponta
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::Request;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, url, total, conc] = &args[..] else {
+        panic!("usage: load URL TOTAL CONC");
+    };
+    let url: hyper::Uri = url.parse().unwrap();
+    let total: usize = total.parse().unwrap();
+    let conc: usize = conc.parse().unwrap();
+
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+    let next = Arc::new(AtomicUsize::new(0));
+    // correlationIds distintos entre execuções (o p99 recusa repetidos)
+    let run = std::process::id() as usize * 1_000_000;
+    let start = Instant::now();
+
+    let tasks: Vec<_> = (0..conc)
+        .map(|_| {
+            let client = client.clone();
+            let next = Arc::clone(&next);
+            let url = url.clone();
+            tokio::spawn(async move {
+                let mut latencies = Vec::new();
+                let mut errors = 0usize;
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= total {
+                        break;
+                    }
+                    let body = format!(
+                        r#"{{"correlationId":"00000000-0000-4000-8000-{:012}","amount":19.90}}"#,
+                        run + i
+                    );
+                    let req = Request::post(url.clone())
+                        .header("content-type", "application/json")
+                        .body(Full::new(Bytes::from(body)))
+                        .unwrap();
+                    let t = Instant::now();
+                    match client.request(req).await {
+                        Ok(resp) => {
+                            let ok = resp.status().is_success();
+                            let _ = resp.into_body().collect().await;
+                            if ok {
+                                latencies.push(t.elapsed().as_micros() as u64);
+                            } else {
+                                errors += 1;
+                            }
+                        }
+                        Err(_) => errors += 1,
+                    }
+                }
+                (latencies, errors)
+            })
+        })
+        .collect();
+
+    let mut all = Vec::new();
+    let mut errors = 0;
+    for task in tasks {
+        let (latencies, e) = task.await.unwrap();
+        all.extend(latencies);
+        errors += e;
+    }
+    let secs = start.elapsed().as_secs_f64();
+    all.sort_unstable();
+    let q = |p: f64| {
+        let i = ((all.len() as f64 * p) as usize).min(all.len().saturating_sub(1));
+        all.get(i).copied().unwrap_or(0) as f64 / 1000.0
+    };
+    println!(
+        "ok={} err={errors} rps={:.0} p50={:.2}ms p90={:.2}ms p99={:.2}ms max={:.2}ms",
+        all.len(),
+        all.len() as f64 / secs,
+        q(0.5),
+        q(0.9),
+        q(0.99),
+        q(1.0)
+    );
+}
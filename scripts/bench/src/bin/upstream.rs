@@ -0,0 +1,33 @@
+/// Processador falso: responde 200 na hora a qualquer requisição
+/// Uso: upstream PORTA
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Request, Response, body::Incoming, service::service_fn};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use tokio::net::TcpListener;
+
+const BODY: &[u8] = br#"{"message":"payment processed successfully"}"#;
+
+async fn handle(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    // Corpo consumido para a conexão seguir em keep-alive
+    let _ = req.into_body().collect().await;
+    Ok(Response::new(Full::new(Bytes::from_static(BODY))))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let port = std::env::args().nth(1).expect("usage: upstream PORT");
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}")).await.unwrap();
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        stream.set_nodelay(true).unwrap();
+        tokio::spawn(async move {
+            let _ = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service_fn(handle))
+                .await;
+        });
+    }
+}
@@ -0,0 +1,445 @@
+/// Ledger de saldos por cliente (Rinha 2024)
+/// Cada cliente tem seu próprio Mutex: débitos e créditos do mesmo cliente
+/// são serializados, enquanto clientes diferentes não disputam lock
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::wal::ContaSnapshot;
+
+/// Quantidade de transações mantidas no extrato
+pub const HISTORY_LEN: usize = 10;
+
+/// Tipo da transação
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tipo {
+    /// Crédito
+    #[serde(rename = "c")]
+    Credito,
+    /// Débito
+    #[serde(rename = "d")]
+    Debito,
+}
+
+impl Tipo {
+    /// Interpreta o campo `tipo` da requisição (`c` ou `d`)
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "c" => Some(Self::Credito),
+            "d" => Some(Self::Debito),
+            _ => None,
+        }
+    }
+
+    /// Efeito do valor no saldo (crédito soma, débito subtrai)
+    pub fn delta(self, valor: i64) -> i64 {
+        match self {
+            Self::Credito => valor,
+            Self::Debito => -valor,
+        }
+    }
+}
+
+/// Transação confirmada de um cliente
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Transacao {
+    /// Valor em centavos
+    pub valor: i64,
+    pub tipo: Tipo,
+    pub descricao: String,
+    /// Momento da confirmação (RFC 3339)
+    pub realizada_em: String,
+}
+
+/// Erros de operação no ledger
+#[derive(Debug, thiserror::Error)]
+pub enum AccountError {
+    #[error("cliente not found")]
+    NotFound,
+    #[error("limite exceeded")]
+    LimitExceeded,
+    /// Saldo resultante não cabe em i64
+    #[error("valor overflow")]
+    Overflow,
+}
+
+/// Conta de um cliente
+struct Conta {
+    /// Limite de crédito (saldo mínimo = -limite), em centavos
+    limite: i64,
+    /// Saldo confirmado em centavos
+    saldo: i64,
+    /// Débitos reservados aguardando confirmação do upstream
+    reservado: i64,
+    /// Créditos reservados aguardando confirmação do upstream
+    a_creditar: i64,
+    /// Últimas transações confirmadas (mais recente primeiro)
+    historico: VecDeque<Transacao>,
+}
+
+impl Conta {
+    /// Registra transação no histórico limitado
+    fn push_historico(&mut self, t: Transacao) {
+        self.historico.push_front(t);
+        self.historico.truncate(HISTORY_LEN);
+    }
+}
+
+/// Saldo e limite após a confirmação de uma transação
+pub struct Posicao {
+    pub limite: i64,
+    pub saldo: i64,
+}
+
+/// Extrato no formato da Rinha 2024
+#[derive(Serialize)]
+pub struct Extrato {
+    pub saldo: SaldoExtrato,
+    pub ultimas_transacoes: Vec<Transacao>,
+}
+
+/// Bloco `saldo` do extrato
+#[derive(Serialize)]
+pub struct SaldoExtrato {
+    pub total: i64,
+    pub data_extrato: String,
+    pub limite: i64,
+}
+
+/// Ledger de contas em memória
+pub struct Accounts {
+    contas: HashMap<i64, Mutex<Conta>>,
+}
+
+impl Accounts {
+    /// Cria contas zeradas com os limites configurados
+    /// # Arguments
+    /// * `limits` - Pares (id do cliente, limite em centavos)
+    pub fn new(limits: &[(i64, i64)]) -> Self {
+        let contas = limits
+            .iter()
+            .map(|&(id, limite)| {
+                let conta = Conta {
+                    limite,
+                    saldo: 0,
+                    reservado: 0,
+                    a_creditar: 0,
+                    historico: VecDeque::with_capacity(HISTORY_LEN),
+                };
+                (id, Mutex::new(conta))
+            })
+            .collect();
+        Self { contas }
+    }
+
+    /// `true` se o cliente tem conta configurada
+    pub fn contains(&self, cliente: i64) -> bool {
+        self.contas.contains_key(&cliente)
+    }
+
+    /// Conta do cliente
+    fn conta(&self, cliente: i64) -> Result<&Mutex<Conta>, AccountError> {
+        self.contas.get(&cliente).ok_or(AccountError::NotFound)
+    }
+
+    /// Passo 1 da saga: reserva o valor antes da chamada ao upstream
+    /// Débitos ficam bloqueados no saldo disponível (saldo - reservado), então
+    /// requisições concorrentes não conseguem ultrapassar o limite juntas.
+    /// Créditos também são reservados para que o commit nunca estoure o saldo
+    pub fn reserve(
+        &self,
+        cliente: i64,
+        tipo: Tipo,
+        valor: i64,
+    ) -> Result<Reserva<'_>, AccountError> {
+        let conta = self.conta(cliente)?;
+
+        let mut c = conta.lock().unwrap();
+        match tipo {
+            Tipo::Debito => {
+                let disponivel = c
+                    .saldo
+                    .checked_sub(c.reservado)
+                    .and_then(|d| d.checked_sub(valor))
+                    .ok_or(AccountError::Overflow)?;
+                if disponivel < -c.limite {
+                    return Err(AccountError::LimitExceeded);
+                }
+                c.reservado = c
+                    .reservado
+                    .checked_add(valor)
+                    .ok_or(AccountError::Overflow)?;
+            }
+            Tipo::Credito => {
+                // saldo + todos os créditos pendentes precisa caber em i64
+                let a_creditar = c
+                    .a_creditar
+                    .checked_add(valor)
+                    .filter(|&a| c.saldo.checked_add(a).is_some())
+                    .ok_or(AccountError::Overflow)?;
+                c.a_creditar = a_creditar;
+            }
+        }
+        drop(c);
+
+        Ok(Reserva {
+            conta,
+            tipo,
+            valor,
+            done: false,
+        })
+    }
+
+    /// Restaura saldo e extrato reconstruídos do WAL, ignorando o limite
+    pub fn restore(&self, cliente: i64, snapshot: ContaSnapshot) {
+        if let Some(conta) = self.contas.get(&cliente) {
+            let mut conta = conta.lock().unwrap();
+            conta.saldo = snapshot.saldo;
+            conta.historico = snapshot.historico.into();
+            conta.historico.truncate(HISTORY_LEN);
+        }
+    }
+
+    /// Extrato do cliente: saldo, limite e últimas transações
+    /// Lidos sob o mesmo lock para uma visão consistente
+    pub fn extrato(&self, cliente: i64, data_extrato: String) -> Result<Extrato, AccountError> {
+        let conta = self.conta(cliente)?.lock().unwrap();
+
+        Ok(Extrato {
+            saldo: SaldoExtrato {
+                total: conta.saldo,
+                data_extrato,
+                limite: conta.limite,
+            },
+            ultimas_transacoes: conta.historico.iter().cloned().collect(),
+        })
+    }
+}
+
+/// Reserva de uma transação em andamento
+/// Deve terminar em `commit` (upstream confirmou) ou `release` (upstream falhou);
+/// se for descartada antes disso (ex: cliente desconectou), é liberada no Drop
+pub struct Reserva<'a> {
+    conta: &'a Mutex<Conta>,
+    tipo: Tipo,
+    valor: i64,
+    /// Reserva já finalizada
+    done: bool,
+}
+
+impl Reserva<'_> {
+    /// Remove desta conta o valor bloqueado pela reserva
+    fn unreserve_in(&self, conta: &mut Conta) {
+        match self.tipo {
+            Tipo::Debito => conta.reservado -= self.valor,
+            Tipo::Credito => conta.a_creditar -= self.valor,
+        }
+    }
+
+    /// Passo 3a da saga: efetiva a transação no saldo e no extrato
+    pub fn commit(mut self, descricao: String, realizada_em: String) -> (Posicao, Transacao) {
+        self.done = true;
+        let transacao = Transacao {
+            valor: self.valor,
+            tipo: self.tipo,
+            descricao,
+            realizada_em,
+        };
+
+        let mut conta = self.conta.lock().unwrap();
+        self.unreserve_in(&mut conta);
+        // A reserva garantiu que o saldo resultante cabe em i64
+        conta.saldo = conta
+            .saldo
+            .checked_add(self.tipo.delta(self.valor))
+            .expect("reserved transacao overflows saldo");
+        conta.push_historico(transacao.clone());
+
+        let posicao = Posicao {
+            limite: conta.limite,
+            saldo: conta.saldo,
+        };
+        (posicao, transacao)
+    }
+
+    /// Passo 3b da saga: devolve o valor reservado sem alterar o saldo
+    pub fn release(mut self) {
+        self.done = true;
+        self.unreserve();
+    }
+
+    /// Remove o bloqueio do valor
+    fn unreserve(&self) {
+        self.unreserve_in(&mut self.conta.lock().unwrap());
+    }
+}
+
+impl Drop for Reserva<'_> {
+    /// Garante que uma reserva abandonada não bloqueie o saldo para sempre
+    fn drop(&mut self) {
+        if !self.done {
+            self.unreserve();
+            metrics::counter!("transacao_saga", "step" => "release", "outcome" => "dropped")
+                .increment(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use super::*;
+
+    /// Reserva e confirma na hora, como faz a saga quando o upstream responde
+    fn apply(accounts: &Accounts, tipo: Tipo, valor: i64) -> Result<Posicao, AccountError> {
+        let reserva = accounts.reserve(1, tipo, valor)?;
+        Ok(reserva.commit(format!("t{valor}"), String::new()).0)
+    }
+
+    #[test]
+    fn debit_past_the_limit_is_rejected() {
+        let accounts = Accounts::new(&[(1, 1000)]);
+
+        assert_eq!(apply(&accounts, Tipo::Debito, 1000).unwrap().saldo, -1000);
+        let err = apply(&accounts, Tipo::Debito, 1).err().unwrap();
+        assert!(matches!(err, AccountError::LimitExceeded));
+
+        // Rejeição não altera saldo nem extrato
+        let extrato = accounts.extrato(1, String::new()).unwrap();
+        assert_eq!(extrato.saldo.total, -1000);
+        assert_eq!(extrato.ultimas_transacoes.len(), 1);
+    }
+
+    #[test]
+    fn unknown_cliente_is_not_found() {
+        let accounts = Accounts::new(&[(1, 1000)]);
+        let err = accounts.reserve(6, Tipo::Credito, 1).err().unwrap();
+        assert!(matches!(err, AccountError::NotFound));
+    }
+
+    #[test]
+    fn concurrent_debits_never_pass_the_limit() {
+        let accounts = Accounts::new(&[(1, 1000)]);
+        let start = Barrier::new(25);
+
+        // 25 débitos de 100 contra limite 1000: exatamente 10 passam
+        let ok = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..25)
+                .map(|_| {
+                    s.spawn(|| {
+                        start.wait();
+                        apply(&accounts, Tipo::Debito, 100).is_ok()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|&ok| ok)
+                .count()
+        });
+        assert_eq!(ok, 10);
+        let extrato = accounts.extrato(1, String::new()).unwrap();
+        assert_eq!(extrato.saldo.total, -1000);
+    }
+
+    #[test]
+    fn credits_accumulate() {
+        let accounts = Accounts::new(&[(1, 0)]);
+
+        for valor in [100, 250, 650] {
+            apply(&accounts, Tipo::Credito, valor).unwrap();
+        }
+        // Créditos ampliam o saldo disponível para débitos mesmo com limite zero
+        assert_eq!(apply(&accounts, Tipo::Debito, 1000).unwrap().saldo, 0);
+
+        let extrato = accounts.extrato(1, String::new()).unwrap();
+        let valores: Vec<_> = extrato.ultimas_transacoes.iter().map(|t| t.valor).collect();
+        assert_eq!(valores, [1000, 650, 250, 100]);
+    }
+
+    #[test]
+    fn overflowing_transacoes_are_rejected() {
+        let accounts = Accounts::new(&[(1, i64::MAX)]);
+
+        // saldo - reservado - valor estouraria i64
+        let r = accounts.reserve(1, Tipo::Debito, i64::MAX).unwrap();
+        let err = accounts.reserve(1, Tipo::Debito, 2).err().unwrap();
+        assert!(matches!(err, AccountError::Overflow));
+        r.release();
+
+        // Créditos pendentes somados ao saldo estourariam i64
+        let r = accounts.reserve(1, Tipo::Credito, i64::MAX).unwrap();
+        let err = accounts.reserve(1, Tipo::Credito, 1).err().unwrap();
+        assert!(matches!(err, AccountError::Overflow));
+        r.commit("max".into(), String::new());
+        let err = accounts.reserve(1, Tipo::Credito, 1).err().unwrap();
+        assert!(matches!(err, AccountError::Overflow));
+
+        let extrato = accounts.extrato(1, String::new()).unwrap();
+        assert_eq!(extrato.saldo.total, i64::MAX);
+    }
+
+    // ========== RESERVA ==========
+
+    /// (saldo, reservado) da conta 1
+    fn posicao(accounts: &Accounts) -> (i64, i64) {
+        let c = accounts.conta(1).unwrap().lock().unwrap();
+        (c.saldo, c.reservado)
+    }
+
+    #[test]
+    fn released_or_dropped_reserva_frees_the_amount() {
+        let accounts = Accounts::new(&[(1, 1000)]);
+
+        // Upstream falhou: release
+        let r = accounts.reserve(1, Tipo::Debito, 600).unwrap();
+        assert_eq!(posicao(&accounts), (0, 600));
+        r.release();
+        assert_eq!(posicao(&accounts), (0, 0));
+
+        // Cliente desconectou no meio da saga: Drop
+        let r = accounts.reserve(1, Tipo::Debito, 600).unwrap();
+        drop(r);
+        assert_eq!(posicao(&accounts), (0, 0));
+
+        // O limite inteiro volta a estar disponível
+        assert_eq!(apply(&accounts, Tipo::Debito, 1000).unwrap().saldo, -1000);
+    }
+
+    #[test]
+    fn pending_reserva_blocks_concurrent_debit() {
+        let accounts = Accounts::new(&[(1, 1000)]);
+
+        let pending = accounts.reserve(1, Tipo::Debito, 700).unwrap();
+        let other = std::thread::scope(|s| {
+            s.spawn(|| accounts.reserve(1, Tipo::Debito, 400).err())
+                .join()
+                .unwrap()
+        });
+        assert!(matches!(other, Some(AccountError::LimitExceeded)));
+
+        // Cabe no que sobra do limite
+        let fits = accounts.reserve(1, Tipo::Debito, 300).unwrap();
+        assert_eq!(posicao(&accounts), (0, 1000));
+        fits.release();
+        pending.release();
+    }
+
+    #[test]
+    fn commit_moves_reservado_into_saldo_once() {
+        let accounts = Accounts::new(&[(1, 1000)]);
+
+        let r = accounts.reserve(1, Tipo::Debito, 400).unwrap();
+        let (posicao_commit, transacao) = r.commit("x".into(), String::new());
+        assert_eq!(posicao_commit.saldo, -400);
+        assert_eq!(transacao.valor, 400);
+
+        // Commit consome a reserva: o Drop não devolve o valor de novo
+        assert_eq!(posicao(&accounts), (-400, 0));
+        let extrato = accounts.extrato(1, String::new()).unwrap();
+        assert_eq!(extrato.ultimas_transacoes.len(), 1);
+    }
+}
@@ -0,0 +1,163 @@
+/// Agregação do `/payments-summary` entre instâncias da API
+/// Cada instância guarda apenas os pagamentos que passaram por ela;
+/// o resumo consulta `/internal/payments-summary` dos peers e soma tudo,
+/// e o `/purge-payments` é repassado a `/internal/purge-payments` de cada peer.
+/// Saldos não podem ser somados: `/clientes/...` vai para a instância dona
+/// (`ACCOUNTS_OWNER`) em `/internal/clientes/...`
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::http::{HeaderValue, Method, StatusCode, header::CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use reqwest::Client;
+
+use crate::config::Cfg;
+use crate::stats::PaymentSummary;
+
+/// Path interno que devolve apenas as estatísticas locais
+pub const INTERNAL_SUMMARY_PATH: &str = "/internal/payments-summary";
+
+/// Path interno que zera apenas as estatísticas locais
+pub const INTERNAL_PURGE_PATH: &str = "/internal/purge-payments";
+
+/// Prefixo interno das rotas de clientes, sempre atendidas localmente
+pub const INTERNAL_CLIENTES_PATH: &str = "/internal/clientes";
+
+/// Cliente para os demais peers do cluster
+pub struct Cluster {
+    /// URLs base dos peers (ex: http://api-2:9999)
+    peers: Vec<String>,
+    /// Cliente HTTP com timeout total por consulta
+    http: Client,
+    /// Timeout das requisições repassadas ao dono dos saldos
+    owner_timeout: Duration,
+}
+
+/// Resposta do dono dos saldos, devolvida ao cliente sem alteração
+pub struct Forwarded {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+impl IntoResponse for Forwarded {
+    fn into_response(self) -> Response {
+        let mut resp = (self.status, self.body).into_response();
+        if let Some(content_type) = self.content_type {
+            resp.headers_mut().insert(CONTENT_TYPE, content_type);
+        }
+        resp
+    }
+}
+
+impl Cluster {
+    /// Cria cliente a partir da lista de peers configurada
+    pub fn new(cfg: &Cfg) -> anyhow::Result<Self> {
+        let http = Client::builder()
+            .tcp_nodelay(true)
+            .timeout(Duration::from_millis(cfg.peer_timeout_ms))
+            .build()?;
+
+        Ok(Self {
+            peers: cfg.peers.clone(),
+            http,
+            owner_timeout: Duration::from_millis(cfg.accounts_owner_timeout_ms),
+        })
+    }
+
+    /// Consulta o resumo local de todos os peers em paralelo
+    /// # Arguments
+    /// * `from` / `to` - Janela repassada sem alteração aos peers
+    ///
+    /// # Returns
+    /// * `Ok(resumos)` - Um resumo por peer
+    /// * `Err` - Primeiro peer que falhou (nunca devolve resultado parcial)
+    pub async fn fetch_all(
+        &self,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> anyhow::Result<Vec<PaymentSummary>> {
+        let mut query = Vec::new();
+        if let Some(from) = from {
+            query.push(("from", from));
+        }
+        if let Some(to) = to {
+            query.push(("to", to));
+        }
+
+        let calls = self.peers.iter().map(|peer| {
+            let req = self
+                .http
+                .get(format!("{peer}{INTERNAL_SUMMARY_PATH}"))
+                .query(&query);
+            async move {
+                let resp = req
+                    .send()
+                    .await
+                    .with_context(|| format!("peer {peer} unreachable"))?
+                    .error_for_status()
+                    .with_context(|| format!("peer {peer} failed"))?;
+                resp.json::<PaymentSummary>()
+                    .await
+                    .with_context(|| format!("peer {peer} returned invalid summary"))
+            }
+        });
+
+        futures::future::join_all(calls).await.into_iter().collect()
+    }
+
+    /// Zera as estatísticas locais de todos os peers em paralelo
+    /// # Returns
+    /// * `Err` - Primeiro peer que falhou (os demais já foram zerados)
+    pub async fn purge_all(&self) -> anyhow::Result<()> {
+        let calls = self.peers.iter().map(|peer| {
+            let req = self.http.post(format!("{peer}{INTERNAL_PURGE_PATH}"));
+            async move {
+                req.send()
+                    .await
+                    .with_context(|| format!("peer {peer} unreachable"))?
+                    .error_for_status()
+                    .with_context(|| format!("peer {peer} failed"))?;
+                Ok(())
+            }
+        });
+
+        futures::future::join_all(calls).await.into_iter().collect()
+    }
+
+    /// Repassa uma requisição de `/clientes/...` à instância dona dos saldos
+    /// # Arguments
+    /// * `owner` - URL base do dono (`ACCOUNTS_OWNER`)
+    /// * `path` - Path abaixo de `/clientes` (ex: `/1/transacoes`)
+    ///
+    /// # Returns
+    /// * `Ok` - Resposta do dono, qualquer que seja o status
+    /// * `Err` - Dono inacessível ou sem resposta no prazo
+    pub async fn forward_accounts(
+        &self,
+        owner: &str,
+        method: Method,
+        path: &str,
+        body: Bytes,
+    ) -> anyhow::Result<Forwarded> {
+        let resp = self
+            .http
+            .request(method, format!("{owner}{INTERNAL_CLIENTES_PATH}{path}"))
+            .timeout(self.owner_timeout)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("accounts owner {owner} unreachable"))?;
+
+        Ok(Forwarded {
+            status: resp.status(),
+            content_type: resp.headers().get(CONTENT_TYPE).cloned(),
+            body: resp
+                .bytes()
+                .await
+                .with_context(|| format!("accounts owner {owner} failed"))?,
+        })
+    }
+}
@@ -1,8 +1,15 @@
 /// Configurações da aplicação load balancer
 /// Todas as configurações são carregadas de variáveis de ambiente
 /// Valores padrão são fornecidos para desenvolvimento
+use std::time::Duration;
+
 use anyhow::Context;
 
+use crate::dispatch::HedgePolicy;
+use crate::mapping::{self, PayloadTemplate};
+use crate::upstream::UpstreamBackend;
+use crate::wal::FsyncPolicy;
+
 /// Estrutura principal de configurações da aplicação
 /// Centraliza todas as opções de tuning e endpoints
 #[allow(unused)]
@@ -11,12 +18,39 @@ pub struct Cfg {
     /// Porta HTTP onde o servidor irá escutar
     pub port: u16,
 
+    /// Unix domain socket escutado além da porta TCP (ex: nginx `proxy_pass http://unix:`)
+    pub listen_unix: Option<String>,
+
     /// URL base do processador A (primário)
     pub upstream_a: String,
 
     /// URL base do processador B (secundário/fallback)
     pub upstream_b: String,
 
+    /// Credenciais enviadas ao processador A
+    pub upstream_a_auth: UpstreamAuth,
+
+    /// Credenciais enviadas ao processador B
+    pub upstream_b_auth: UpstreamAuth,
+
+    /// Assinatura das requisições ao processador A
+    pub upstream_a_signing: UpstreamSigning,
+
+    /// Assinatura das requisições ao processador B
+    pub upstream_b_signing: UpstreamSigning,
+
+    /// Contrato (corpo enviado e resposta) do processador A
+    pub upstream_a_mapping: UpstreamMapping,
+
+    /// Contrato (corpo enviado e resposta) do processador B
+    pub upstream_b_mapping: UpstreamMapping,
+
+    /// Timeouts, pool e protocolo das conexões com o processador A
+    pub upstream_a_transport: UpstreamTransport,
+
+    /// Timeouts, pool e protocolo das conexões com o processador B
+    pub upstream_b_transport: UpstreamTransport,
+
     /// Path da API de pagamento nos processadores upstream
     pub pay_path: String,
 
@@ -26,11 +60,38 @@ pub struct Cfg {
     /// Valor do header de autenticação (opcional)
     pub auth_header_value: Option<String>,
 
-    /// Timeout total para requisições HTTP (milissegundos)
+    /// Timeout total para requisições HTTP (milissegundos, padrão dos processadores)
     pub request_timeout_ms: u64,
 
-    /// Delay antes de iniciar hedging (milissegundos)
-    pub hedge_delay_ms: u64,
+    /// Política de hedging do /payments
+    pub pay_hedge: HedgePolicy,
+
+    /// Política de hedging do /clientes/{id}/transacoes
+    pub transacao_hedge: HedgePolicy,
+
+    /// Tentativas por chamada ao processador (1 = sem retry)
+    pub upstream_retry_max_attempts: u32,
+
+    /// Backoff da primeira retentativa ao processador (milissegundos)
+    pub upstream_retry_base_backoff_ms: u64,
+
+    /// Teto do backoff entre retentativas ao processador (milissegundos)
+    pub upstream_retry_max_backoff_ms: u64,
+
+    /// Fração máxima de retries em relação às requisições (0.1 = 10%)
+    pub upstream_retry_budget_ratio: f64,
+
+    /// Retries disponíveis no orçamento logo após o boot
+    pub upstream_retry_min_reserve: u32,
+
+    /// Backend HTTP das chamadas aos processadores (reqwest ou hyper)
+    pub upstream_backend: UpstreamBackend,
+
+    /// Conexões keep-alive abertas por processador no startup e na recuperação do breaker
+    pub upstream_warm_conns: usize,
+
+    /// Path requisitado (GET) para abrir conexões no backend reqwest
+    pub upstream_warm_path: String,
 
     /// Limite máximo de conexões concorrentes
     pub concurrency_limit: usize,
@@ -43,55 +104,236 @@ pub struct Cfg {
 
     /// Tempo que circuit breaker fica aberto (segundos)
     pub cb_open_secs: u64,
+
+    /// Capacidade máxima da fila de retry de pagamentos
+    pub retry_queue_capacity: usize,
+
+    /// Número máximo de tentativas na fila de retry antes de descartar
+    pub retry_max_attempts: u32,
+
+    /// Backoff inicial entre tentativas da fila de retry (milissegundos)
+    pub retry_base_backoff_ms: u64,
+
+    /// Backoff máximo entre tentativas da fila de retry (milissegundos)
+    pub retry_max_backoff_ms: u64,
+
+    /// Intervalo de varredura do worker da fila de retry (milissegundos)
+    pub retry_tick_ms: u64,
+
+    /// Caminho do write-ahead log de pagamentos (opcional, desabilitado se ausente)
+    pub wal_path: Option<String>,
+
+    /// Política de fsync do WAL (always, interval ou never)
+    pub wal_fsync: FsyncPolicy,
+
+    /// URLs base das demais instâncias da API (agregação do resumo)
+    pub peers: Vec<String>,
+
+    /// Timeout da consulta de resumo a cada peer (milissegundos)
+    pub peer_timeout_ms: u64,
+
+    /// Arquivo do ledger compartilhado em volume comum (opcional)
+    pub ledger_path: Option<String>,
+
+    /// Capacidade do ledger compartilhado (pagamentos mais recentes mantidos)
+    pub ledger_capacity: u64,
+
+    /// Token `X-Rinha-Token` dos endpoints administrativos dos processadores
+    pub admin_token: String,
+
+    /// Intervalo do job de reconciliação (segundos, 0 = desabilitado)
+    pub reconcile_interval_secs: u64,
+
+    /// Janela verificada pelo job de reconciliação (segundos)
+    pub reconcile_window_secs: u64,
+
+    /// Timeout das consultas de reconciliação (milissegundos)
+    pub reconcile_timeout_ms: u64,
+
+    /// Limites de crédito por cliente: (id, limite em centavos)
+    pub client_limits: Vec<(i64, i64)>,
+
+    /// URL base da instância dona dos saldos (ausente = esta instância)
+    pub accounts_owner: Option<String>,
+
+    /// Timeout das requisições repassadas ao dono dos saldos (milissegundos)
+    pub accounts_owner_timeout_ms: u64,
 }
 
 impl Cfg {
     /// Carrega configurações de variáveis de ambiente
     /// Fornece valores padrão para desenvolvimento
     pub fn from_env() -> anyhow::Result<Self> {
+        // Prazo padrão do hedge das rotas ({PREFIX}_HEDGE_DELAY_MS sobrescreve)
+        let hedge_delay_ms = var("HEDGE_DELAY_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(40); // 40ms para hedging
+        let request_timeout_ms = var("REQUEST_TIMEOUT_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(120); // 120ms timeout padrão
+
         Ok(Self {
             // ========== CONFIGURAÇÃO DO SERVIDOR ==========
-            port: std::env::var("PORT")
+            port: var("PORT")
                 .ok()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(9999), // Porta padrão para desenvolvimento
+            listen_unix: var("LISTEN_UNIX").ok(), // Desabilitado por padrão
 
             // ========== ENDPOINTS DOS PROCESSADORES ==========
-            upstream_a: std::env::var("UPSTREAM_A_URL").context("UPSTREAM_A_URL missing")?, // Obrigatório
-            upstream_b: std::env::var("UPSTREAM_B_URL").context("UPSTREAM_B_URL missing")?, // Obrigatório
-            pay_path: std::env::var("UPSTREAM_PAY_PATH").unwrap_or_else(|_| "/api/pay".into()), // Path padrão
+            upstream_a: var("UPSTREAM_A_URL").context("UPSTREAM_A_URL missing")?, // Obrigatório
+            upstream_b: var("UPSTREAM_B_URL").context("UPSTREAM_B_URL missing")?, // Obrigatório
+            upstream_a_auth: UpstreamAuth::from_env("UPSTREAM_A")?,
+            upstream_b_auth: UpstreamAuth::from_env("UPSTREAM_B")?,
+            upstream_a_signing: UpstreamSigning::from_env("UPSTREAM_A")?,
+            upstream_b_signing: UpstreamSigning::from_env("UPSTREAM_B")?,
+            upstream_a_mapping: UpstreamMapping::from_env("UPSTREAM_A")?,
+            upstream_b_mapping: UpstreamMapping::from_env("UPSTREAM_B")?,
+            upstream_a_transport: UpstreamTransport::from_env("UPSTREAM_A", request_timeout_ms)?,
+            upstream_b_transport: UpstreamTransport::from_env("UPSTREAM_B", request_timeout_ms)?,
+            pay_path: var("UPSTREAM_PAY_PATH").unwrap_or_else(|_| "/api/pay".into()), // Path padrão
 
             // ========== AUTENTICAÇÃO ==========
-            auth_header_name: std::env::var("AUTH_HEADER_NAME").ok(),
-            auth_header_value: std::env::var("AUTH_HEADER_VALUE").ok(),
+            auth_header_name: var("AUTH_HEADER_NAME").ok(),
+            auth_header_value: var("AUTH_HEADER_VALUE").ok(),
 
             // ========== TIMEOUTS E PERFORMANCE ==========
-            request_timeout_ms: std::env::var("REQUEST_TIMEOUT_MS")
+            request_timeout_ms,
+            pay_hedge: hedge_policy("PAY", "sequential-fallback", hedge_delay_ms)?,
+            transacao_hedge: hedge_policy("TRANSACAO", "parallel-after-delay", hedge_delay_ms)?,
+            upstream_retry_max_attempts: var("UPSTREAM_RETRY_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(2), // Um retry por chamada
+            upstream_retry_base_backoff_ms: var("UPSTREAM_RETRY_BASE_BACKOFF_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(5), // 5ms antes do primeiro retry (com jitter)
+            upstream_retry_max_backoff_ms: var("UPSTREAM_RETRY_MAX_BACKOFF_MS")
                 .ok()
                 .and_then(|s| s.parse().ok())
-                .unwrap_or(120), // 120ms timeout padrão
-            hedge_delay_ms: std::env::var("HEDGE_DELAY_MS")
+                .unwrap_or(50), // No máximo 50ms entre tentativas
+            upstream_retry_budget_ratio: var("UPSTREAM_RETRY_BUDGET_RATIO")
                 .ok()
                 .and_then(|s| s.parse().ok())
-                .unwrap_or(40), // 40ms para hedging
-            concurrency_limit: std::env::var("CONCURRENCY_LIMIT")
+                .unwrap_or(0.1), // Retries <= 10% das requisições
+            upstream_retry_min_reserve: var("UPSTREAM_RETRY_MIN_RESERVE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(10), // 10 retries livres após o boot
+            upstream_backend: UpstreamBackend::parse(
+                &var("UPSTREAM_BACKEND").unwrap_or_else(|_| "reqwest".into()),
+            )
+            .context("invalid UPSTREAM_BACKEND")?,
+            upstream_warm_conns: var("UPSTREAM_WARM_CONNS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(8), // 8 conexões prontas por processador
+            upstream_warm_path: var("UPSTREAM_WARM_PATH").unwrap_or_else(|_| "/".into()), // Qualquer resposta serve
+            concurrency_limit: var("CONCURRENCY_LIMIT")
                 .ok()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(1024), // 1024 conexões concorrentes
 
             // ========== CIRCUIT BREAKER ==========
-            cb_fail_rate: std::env::var("CB_FAIL_RATE")
+            cb_fail_rate: var("CB_FAIL_RATE")
                 .ok()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(0.25), // 25% de falha abre circuito
-            cb_min_samples: std::env::var("CB_MIN_SAMPLES")
+            cb_min_samples: var("CB_MIN_SAMPLES")
                 .ok()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(50), // Mínimo 50 amostras
-            cb_open_secs: std::env::var("CB_OPEN_SECS")
+            cb_open_secs: var("CB_OPEN_SECS")
                 .ok()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(2), // 2 segundos aberto
+
+            // ========== FILA DE RETRY ==========
+            retry_queue_capacity: var("RETRY_QUEUE_CAPACITY")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(10_000), // 10k pagamentos pendentes no máximo
+            retry_max_attempts: var("RETRY_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(10), // 10 tentativas antes de desistir
+            retry_base_backoff_ms: var("RETRY_BASE_BACKOFF_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(50), // 50ms na primeira retentativa
+            retry_max_backoff_ms: var("RETRY_MAX_BACKOFF_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(2_000), // No máximo 2s entre tentativas
+            retry_tick_ms: var("RETRY_TICK_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(20), // Varredura a cada 20ms
+
+            // ========== WRITE-AHEAD LOG ==========
+            wal_path: var("WAL_PATH").ok(),
+            wal_fsync: FsyncPolicy::parse(
+                &var("WAL_FSYNC").unwrap_or_else(|_| "interval".into()),
+                var("WAL_FSYNC_INTERVAL_MS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(100), // fsync a cada 100ms no modo interval
+            )?,
+
+            // ========== CLUSTER ==========
+            peers: var("PEERS")
+                .map(|s| {
+                    s.split(',')
+                        .map(|p| p.trim().trim_end_matches('/').to_string())
+                        .filter(|p| !p.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(), // Sem peers: resumo apenas local
+            peer_timeout_ms: var("PEER_TIMEOUT_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(500), // 500ms por peer
+
+            // ========== LEDGER COMPARTILHADO ==========
+            ledger_path: var("LEDGER_PATH").ok(),
+            ledger_capacity: var("LEDGER_CAPACITY")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(2_000_000), // ~48MB de arquivo (esparso)
+
+            // ========== RECONCILIAÇÃO ==========
+            admin_token: var("UPSTREAM_ADMIN_TOKEN").unwrap_or_else(|_| "123".into()), // Token padrão da Rinha
+            reconcile_interval_secs: var("RECONCILE_INTERVAL_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0), // Desabilitado por padrão
+            reconcile_window_secs: var("RECONCILE_WINDOW_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(60), // Último minuto
+            reconcile_timeout_ms: var("RECONCILE_TIMEOUT_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(2_000), // 2s por consulta
+
+            // ========== CLIENTES ==========
+            // Formato: "id:limite,id:limite" (padrão: clientes da Rinha 2024)
+            client_limits: parse_client_limits(
+                &var("CLIENT_LIMITS")
+                    .unwrap_or_else(|_| "1:100000,2:80000,3:1000000,4:10000000,5:500000".into()),
+            )?,
+            // Saldo é estado único: com várias instâncias, só uma é dona das contas
+            accounts_owner: var("ACCOUNTS_OWNER")
+                .ok()
+                .map(|s| s.trim().trim_end_matches('/').to_string())
+                .filter(|s| !s.is_empty()),
+            accounts_owner_timeout_ms: var("ACCOUNTS_OWNER_TIMEOUT_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(2_000), // Cobre a chamada ao processador no dono
         })
     }
 
@@ -101,6 +343,413 @@ impl Cfg {
         let mut c = self.clone();
         // Mascarar valor do header de autenticação
         c.auth_header_value = c.auth_header_value.as_ref().map(|_| "***".into());
+        c.admin_token = "***".into();
+        c.upstream_a_auth = c.upstream_a_auth.redacted();
+        c.upstream_b_auth = c.upstream_b_auth.redacted();
+        c.upstream_a_signing = c.upstream_a_signing.redacted();
+        c.upstream_b_signing = c.upstream_b_signing.redacted();
         c
     }
 }
+
+/// Credenciais de saída para um processador upstream
+#[derive(Clone, Debug)]
+pub enum UpstreamAuth {
+    /// Nenhuma credencial
+    None,
+    /// Header arbitrário (ex: `X-Rinha-Token: 123`)
+    Header { name: String, value: String },
+    /// `Authorization: Bearer <token>`
+    Bearer(String),
+    /// `Authorization: Basic <user:password>`
+    Basic {
+        user: String,
+        password: Option<String>,
+    },
+}
+
+impl UpstreamAuth {
+    /// Carrega credenciais de `{PREFIX}_AUTH` (none, header, bearer, basic)
+    /// Segredos podem vir de arquivo via `{VAR}_FILE` (ex: Docker secrets)
+    /// Padrão: header `X-Rinha-Token: 123` dos processadores da Rinha
+    fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let var = |name: &str| var(format!("{prefix}_{name}")).ok();
+        let scheme = var("AUTH").unwrap_or_else(|| "header".into());
+
+        Ok(match scheme.as_str() {
+            "none" => Self::None,
+            "header" => Self::Header {
+                name: var("AUTH_HEADER").unwrap_or_else(|| "X-Rinha-Token".into()),
+                value: secret(&format!("{prefix}_AUTH_VALUE"))?.unwrap_or_else(|| "123".into()),
+            },
+            "bearer" => Self::Bearer(
+                secret(&format!("{prefix}_AUTH_TOKEN"))?
+                    .with_context(|| format!("{prefix}_AUTH_TOKEN missing"))?,
+            ),
+            "basic" => Self::Basic {
+                user: var("AUTH_USER").with_context(|| format!("{prefix}_AUTH_USER missing"))?,
+                password: secret(&format!("{prefix}_AUTH_PASSWORD"))?,
+            },
+            other => anyhow::bail!("invalid {prefix}_AUTH: {other}"),
+        })
+    }
+
+    /// Cópia com os segredos mascarados
+    fn redacted(&self) -> Self {
+        match self {
+            Self::None => Self::None,
+            Self::Header { name, .. } => Self::Header {
+                name: name.clone(),
+                value: "***".into(),
+            },
+            Self::Bearer(_) => Self::Bearer("***".into()),
+            Self::Basic { user, password } => Self::Basic {
+                user: user.clone(),
+                password: password.as_ref().map(|_| "***".into()),
+            },
+        }
+    }
+}
+
+/// Versão HTTP usada com um processador
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// Negociada pelo cliente (ALPN com TLS, HTTP/1.1 em texto puro)
+    Auto,
+    /// Somente HTTP/1.1
+    Http1,
+    /// HTTP/2 em texto puro com prior knowledge (sem upgrade nem ALPN)
+    H2c,
+}
+
+impl HttpVersion {
+    /// Nome usado em labels de métricas
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Http1 => "http1",
+            Self::H2c => "h2c",
+        }
+    }
+}
+
+/// Configurações de transporte de um processador upstream
+/// O fallback costuma ser mais lento e instável que o default, então cada
+/// processador tem seus próprios timeouts e pool
+#[derive(Clone, Debug)]
+pub struct UpstreamTransport {
+    /// Timeout do connect TCP
+    pub connect_timeout: Duration,
+    /// Timeout total da requisição
+    pub request_timeout: Duration,
+    /// Conexões ociosas mantidas (no backend hyper, também o máximo de abertas)
+    pub pool_size: usize,
+    /// Tempo máximo de uma conexão ociosa no pool
+    pub pool_idle_timeout: Duration,
+    /// Versão HTTP
+    pub http_version: HttpVersion,
+    /// Conexões compartilhadas em h2c (streams multiplexadas sobre elas)
+    pub h2_connections: usize,
+    /// Reutiliza conexões entre requisições (HTTP keep-alive)
+    pub keepalive: bool,
+    /// Desabilita o algoritmo de Nagle
+    pub tcp_nodelay: bool,
+    /// Unix domain socket do processador no lugar do TCP (host da URL vira só o Host)
+    pub unix_socket: Option<String>,
+    /// TLS do processador (URLs `https://`)
+    pub tls: UpstreamTls,
+}
+
+impl UpstreamTransport {
+    /// Carrega de `{PREFIX}_CONNECT_TIMEOUT_MS`, `{PREFIX}_REQUEST_TIMEOUT_MS`
+    /// (padrão: REQUEST_TIMEOUT_MS), `{PREFIX}_POOL_SIZE`, `{PREFIX}_POOL_IDLE_SECS`,
+    /// `{PREFIX}_HTTP_VERSION` (auto, http1, h2c), `{PREFIX}_H2_CONNECTIONS`,
+    /// `{PREFIX}_KEEPALIVE`, `{PREFIX}_TCP_NODELAY`, `{PREFIX}_UNIX_SOCKET` e `{PREFIX}_TLS_*`
+    fn from_env(prefix: &str, request_timeout_ms: u64) -> anyhow::Result<Self> {
+        let var = |name: &str| var(format!("{prefix}_{name}")).ok();
+        let http_version = match var("HTTP_VERSION").as_deref().unwrap_or("auto") {
+            "auto" => HttpVersion::Auto,
+            "http1" => HttpVersion::Http1,
+            "h2c" => HttpVersion::H2c,
+            other => anyhow::bail!("invalid {prefix}_HTTP_VERSION: {other}"),
+        };
+
+        Ok(Self {
+            connect_timeout: Duration::from_millis(
+                var("CONNECT_TIMEOUT_MS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(25), // Curto: processadores estão na mesma rede
+            ),
+            request_timeout: Duration::from_millis(
+                var("REQUEST_TIMEOUT_MS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(request_timeout_ms),
+            ),
+            pool_size: var("POOL_SIZE").and_then(|s| s.parse().ok()).unwrap_or(32), // Pool grande para alta concorrência
+            pool_idle_timeout: Duration::from_secs(
+                var("POOL_IDLE_SECS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(30), // Keep-alive por 30s
+            ),
+            http_version,
+            h2_connections: var("H2_CONNECTIONS")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(2), // Poucas conexões bastam com multiplexação
+            keepalive: var("KEEPALIVE")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(true),
+            tcp_nodelay: var("TCP_NODELAY")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(true), // Baixa latência
+            unix_socket: var("UNIX_SOCKET"),
+            tls: UpstreamTls::from_env(prefix)?,
+        })
+    }
+}
+
+/// TLS de saída para um processador
+/// Sem nenhuma opção usa as raízes públicas e o host da URL, como antes
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamTls {
+    /// Bundle PEM de CAs confiáveis (substitui as raízes públicas)
+    pub ca_file: Option<String>,
+    /// Certificado cliente PEM para mTLS (com a cadeia, se houver)
+    pub cert_file: Option<String>,
+    /// Chave privada PEM do certificado cliente
+    pub key_file: Option<String>,
+    /// Nome enviado no SNI e validado no certificado (padrão: host da URL)
+    pub server_name: Option<String>,
+    /// SHA-256 aceitos do certificado (DER) do servidor; vazio = sem pinning
+    pub pins: Vec<[u8; 32]>,
+}
+
+impl UpstreamTls {
+    /// Carrega de `{PREFIX}_TLS_CA_FILE`, `{PREFIX}_TLS_CERT_FILE`, `{PREFIX}_TLS_KEY_FILE`,
+    /// `{PREFIX}_TLS_SERVER_NAME` e `{PREFIX}_TLS_PINS` (hex, separados por vírgula)
+    fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let var = |name: &str| var(format!("{prefix}_{name}")).ok();
+        let tls = Self {
+            ca_file: var("TLS_CA_FILE"),
+            cert_file: var("TLS_CERT_FILE"),
+            key_file: var("TLS_KEY_FILE"),
+            server_name: var("TLS_SERVER_NAME"),
+            pins: var("TLS_PINS")
+                .as_deref()
+                .map(parse_pins)
+                .transpose()
+                .with_context(|| format!("invalid {prefix}_TLS_PINS"))?
+                .unwrap_or_default(),
+        };
+        anyhow::ensure!(
+            tls.cert_file.is_some() == tls.key_file.is_some(),
+            "{prefix}_TLS_CERT_FILE and {prefix}_TLS_KEY_FILE must be set together"
+        );
+        Ok(tls)
+    }
+
+    /// `true` se alguma opção exige configuração TLS própria
+    pub fn is_custom(&self) -> bool {
+        self.ca_file.is_some() || self.cert_file.is_some() || !self.pins.is_empty()
+    }
+}
+
+/// Interpreta lista de SHA-256 em hex ("ab:cd..." ou "abcd..."), separados por vírgula
+fn parse_pins(s: &str) -> anyhow::Result<Vec<[u8; 32]>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|pin| {
+            let hex: String = pin.chars().filter(|c| *c != ':').collect();
+            anyhow::ensure!(
+                hex.len() == 64 && hex.is_ascii(),
+                "pin must be a SHA-256 in hex: {pin}"
+            );
+            let mut out = [0u8; 32];
+            for (i, byte) in out.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                    .with_context(|| format!("invalid hex in pin {pin}"))?;
+            }
+            Ok(out)
+        })
+        .collect()
+}
+
+/// Assinatura das requisições de saída para um processador
+#[derive(Clone, Debug)]
+pub enum UpstreamSigning {
+    /// Requisições sem assinatura
+    None,
+    /// HMAC-SHA256 de timestamp, método, path e digest do corpo
+    HmacSha256 {
+        /// Chave compartilhada com o processador
+        key: String,
+        /// Identificador da chave enviado em `X-Key-Id` (opcional)
+        key_id: Option<String>,
+        /// Header da assinatura
+        signature_header: String,
+        /// Header do timestamp (segundos Unix)
+        timestamp_header: String,
+    },
+}
+
+impl UpstreamSigning {
+    /// Carrega de `{PREFIX}_SIGNING` (none, hmac-sha256), `{PREFIX}_SIGNING_KEY`
+    /// (aceita `_FILE`), `{PREFIX}_SIGNING_KEY_ID`, `{PREFIX}_SIGNING_HEADER` e
+    /// `{PREFIX}_SIGNING_TIMESTAMP_HEADER`
+    fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let var = |name: &str| var(format!("{prefix}_{name}")).ok();
+        let scheme = var("SIGNING").unwrap_or_else(|| "none".into());
+
+        Ok(match scheme.as_str() {
+            "none" => Self::None,
+            "hmac-sha256" => Self::HmacSha256 {
+                key: secret(&format!("{prefix}_SIGNING_KEY"))?
+                    .with_context(|| format!("{prefix}_SIGNING_KEY missing"))?,
+                key_id: var("SIGNING_KEY_ID"),
+                signature_header: var("SIGNING_HEADER").unwrap_or_else(|| "X-Signature".into()),
+                timestamp_header: var("SIGNING_TIMESTAMP_HEADER")
+                    .unwrap_or_else(|| "X-Timestamp".into()),
+            },
+            other => anyhow::bail!("invalid {prefix}_SIGNING: {other}"),
+        })
+    }
+
+    /// Cópia com a chave mascarada
+    fn redacted(&self) -> Self {
+        match self {
+            Self::None => Self::None,
+            Self::HmacSha256 {
+                key_id,
+                signature_header,
+                timestamp_header,
+                ..
+            } => Self::HmacSha256 {
+                key: "***".into(),
+                key_id: key_id.clone(),
+                signature_header: signature_header.clone(),
+                timestamp_header: timestamp_header.clone(),
+            },
+        }
+    }
+}
+
+/// Contrato de um processador: template do corpo e onde ler a confirmação
+/// Sem nenhuma opção usa o formato da Rinha
+#[derive(Clone, Debug)]
+pub struct UpstreamMapping {
+    /// Template do corpo de pagamento
+    pub request: PayloadTemplate,
+    /// JSON pointer da mensagem de confirmação na resposta de sucesso
+    pub message_pointer: String,
+}
+
+impl UpstreamMapping {
+    /// Carrega de `{PREFIX}_REQUEST_TEMPLATE` (ou `{PREFIX}_REQUEST_TEMPLATE_FILE`)
+    /// e `{PREFIX}_RESPONSE_MESSAGE_POINTER`
+    fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        let var = |name: &str| var(format!("{prefix}_{name}")).ok();
+        let template = match var("REQUEST_TEMPLATE_FILE") {
+            Some(path) => Some(
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("read {prefix}_REQUEST_TEMPLATE_FILE {path}"))?,
+            ),
+            None => var("REQUEST_TEMPLATE"),
+        };
+        let request = match template {
+            Some(t) => PayloadTemplate::compile(&t)
+                .with_context(|| format!("invalid {prefix}_REQUEST_TEMPLATE"))?,
+            None => PayloadTemplate::default(),
+        };
+
+        let message_pointer = var("RESPONSE_MESSAGE_POINTER")
+            .unwrap_or_else(|| mapping::DEFAULT_MESSAGE_POINTER.into());
+        anyhow::ensure!(
+            message_pointer.is_empty() || message_pointer.starts_with('/'),
+            "invalid {prefix}_RESPONSE_MESSAGE_POINTER: {message_pointer} (must start with /)"
+        );
+
+        Ok(Self {
+            request,
+            message_pointer,
+        })
+    }
+}
+
+/// Lê segredo de `{name}_FILE` (conteúdo do arquivo) ou de `{name}`
+fn secret(name: &str) -> anyhow::Result<Option<String>> {
+    if let Ok(path) = var(format!("{name}_FILE")) {
+        let value =
+            std::fs::read_to_string(&path).with_context(|| format!("read {name}_FILE {path}"))?;
+        return Ok(Some(value.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    Ok(var(name).ok())
+}
+
+/// Interpreta lista de limites no formato "id:limite,id:limite"
+fn parse_client_limits(s: &str) -> anyhow::Result<Vec<(i64, i64)>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let (id, limite) = pair
+                .split_once(':')
+                .with_context(|| format!("invalid CLIENT_LIMITS entry: {pair}"))?;
+            Ok((
+                id.trim().parse().context("invalid CLIENT_LIMITS id")?,
+                limite
+                    .trim()
+                    .parse()
+                    .context("invalid CLIENT_LIMITS limite")?,
+            ))
+        })
+        .collect()
+}
+
+/// Política de hedging de uma rota a partir de `{PREFIX}_HEDGE_MODE`,
+/// `{PREFIX}_HEDGE_DELAY_MS` (padrão: HEDGE_DELAY_MS) e `{PREFIX}_MAX_HEDGES`
+fn hedge_policy(prefix: &str, mode: &str, delay_ms: u64) -> anyhow::Result<HedgePolicy> {
+    let var = |name: &str| var(format!("{prefix}_{name}")).ok();
+    HedgePolicy::parse(
+        &var("HEDGE_MODE").unwrap_or_else(|| mode.into()),
+        var("HEDGE_DELAY_MS")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(delay_ms),
+        var("MAX_HEDGES").and_then(|s| s.parse().ok()).unwrap_or(1), // Um hedge por padrão
+    )
+    .with_context(|| format!("invalid {prefix} hedge policy"))
+}
+
+/// Lê variável de ambiente
+#[cfg(not(test))]
+fn var<K: AsRef<str>>(name: K) -> Result<String, std::env::VarError> {
+    std::env::var(name.as_ref())
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Variáveis vistas por `Cfg::from_vars` (o ambiente do processo é ignorado)
+    static TEST_VARS: std::cell::RefCell<std::collections::HashMap<String, String>> =
+        Default::default();
+}
+
+/// Lê variável de ambiente (nos testes, só as passadas para `Cfg::from_vars`)
+#[cfg(test)]
+fn var<K: AsRef<str>>(name: K) -> Result<String, std::env::VarError> {
+    TEST_VARS
+        .with(|vars| vars.borrow().get(name.as_ref()).cloned())
+        .ok_or(std::env::VarError::NotPresent)
+}
+
+#[cfg(test)]
+impl Cfg {
+    /// Configuração a partir de pares (nome, valor), sem tocar no ambiente do processo
+    pub fn from_vars(vars: &[(&str, &str)]) -> anyhow::Result<Self> {
+        TEST_VARS.with(|v| {
+            *v.borrow_mut() = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        });
+        Self::from_env()
+    }
+}
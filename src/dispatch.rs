@@ -0,0 +1,940 @@
+/// Despacho de pagamentos para os processadores upstream
+/// Concentra a escolha primário/secundário, o hedging, o feedback dos
+/// circuit breakers e o registro das estatísticas, compartilhados por
+/// `pay`, `transacao` e pela fila de retry
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::breaker::Breaker;
+use crate::config::Cfg;
+use crate::money::Money;
+use crate::stats::{self, PaymentStats};
+use crate::strategy::RouteStrategy;
+use crate::upstream::{ProcessorResponse, UpstreamClient, UpstreamError, UpstreamId};
+use crate::wal::{Wal, WalRecord};
+
+/// Intervalo de verificação dos circuitos para o warm-up de recuperação
+const RECOVERY_TICK: Duration = Duration::from_millis(100);
+
+/// Resultado de uma chamada ao upstream (mesmo formato do `UpstreamClient`)
+pub type UpstreamResult = Result<(UpstreamId, ProcessorResponse), UpstreamError>;
+
+/// Modo de hedging de um despacho
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HedgeMode {
+    /// Uma única tentativa no processador escolhido (sem risco de cobrança dupla)
+    Disabled,
+    /// Primário com prazo de `delay`; secundário só depois que ele falha ou estoura
+    SequentialFallback,
+    /// Secundário disparado em paralelo após `delay`; vence o primeiro sucesso
+    ParallelAfterDelay,
+    /// Primário e secundário disparados juntos; vence o primeiro sucesso
+    ParallelImmediate,
+}
+
+/// Política de hedging de uma rota
+#[derive(Clone, Debug)]
+pub struct HedgePolicy {
+    pub mode: HedgeMode,
+    /// Prazo do primário antes do hedge
+    pub delay: Duration,
+    /// Tentativas extras além do primário: 0 (sem hedge) ou 1
+    /// (com dois processadores não há uma segunda extra)
+    pub max_hedges: u32,
+}
+
+impl HedgePolicy {
+    /// Uma tentativa por despacho (usada pela fila de retry)
+    pub const DISABLED: Self = Self {
+        mode: HedgeMode::Disabled,
+        delay: Duration::ZERO,
+        max_hedges: 0,
+    };
+
+    /// Interpreta a política a partir do modo configurado
+    /// # Arguments
+    /// * `mode` - `disabled`, `sequential-fallback`, `parallel-after-delay` ou `parallel-immediate`
+    /// * `delay_ms` - Prazo do primário antes do hedge
+    /// * `max_hedges` - Tentativas extras além do primário (0 ou 1)
+    pub fn parse(mode: &str, delay_ms: u64, max_hedges: u32) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            max_hedges <= 1,
+            "max hedges must be 0 or 1 with two processors (got {max_hedges})"
+        );
+        let mode = match mode {
+            "disabled" => HedgeMode::Disabled,
+            "sequential-fallback" => HedgeMode::SequentialFallback,
+            "parallel-after-delay" => HedgeMode::ParallelAfterDelay,
+            "parallel-immediate" => HedgeMode::ParallelImmediate,
+            other => anyhow::bail!("invalid hedge mode: {other}"),
+        };
+        Ok(Self {
+            mode,
+            delay: Duration::from_millis(delay_ms),
+            max_hedges,
+        })
+    }
+}
+
+/// Pagamento enviado aos processadores
+#[derive(Clone)]
+pub struct Payment {
+    /// correlationId enviado ao upstream (reaproveitado entre tentativas)
+    pub correlation_id: String,
+    /// Valor do pagamento
+    pub amount: Money,
+    /// requestedAt original, mantido para bater com o registro do processador
+    pub requested_at: String,
+}
+
+impl Payment {
+    /// Novo pagamento com correlationId próprio e requestedAt atual
+    pub fn new(amount: Money) -> Self {
+        Self {
+            correlation_id: uuid::Uuid::new_v4().to_string(),
+            amount,
+            requested_at: stats::now_timestamp(),
+        }
+    }
+}
+
+/// Processador candidato: cliente HTTP e seu circuit breaker
+#[derive(Clone)]
+struct Candidate {
+    up: Arc<UpstreamClient>,
+    breaker: Arc<Breaker>,
+}
+
+impl Candidate {
+    /// Executa uma tentativa e notifica o circuit breaker do resultado
+    /// Rejeições 4xx mostram processador saudável e contam como sucesso
+    async fn attempt(self, payment: Payment) -> UpstreamResult {
+        let res = self.up.request(&payment).await;
+        match &res {
+            Ok(_) => self.breaker.on_success(),
+            Err(e) => {
+                metrics::counter!("upstream_errors", "upstream" => e.upstream().as_str(), "kind" => e.kind())
+                    .increment(1);
+                if e.is_upstream_fault() {
+                    self.breaker.on_failure();
+                } else {
+                    self.breaker.on_success();
+                }
+            }
+        }
+        res
+    }
+
+    /// Abre conexões com o processador e registra o resultado
+    async fn warm(&self, conns: usize, reason: &'static str) {
+        let ready = self.up.warm(conns).await;
+        let upstream = self.up.id.as_str();
+        metrics::counter!("upstream_warmups", "upstream" => upstream, "reason" => reason)
+            .increment(1);
+        metrics::gauge!("upstream_warm_connections", "upstream" => upstream).set(ready as f64);
+        info!("upstream {upstream}: warmed {ready}/{conns} connections ({reason})");
+    }
+
+    /// Dispara a tentativa em uma tarefa própria
+    fn spawn(&self, payment: &Payment) -> InFlight {
+        InFlight {
+            upstream: self.up.id,
+            handle: tokio::spawn(self.clone().attempt(payment.clone())),
+        }
+    }
+}
+
+/// Tentativa em andamento numa tarefa própria
+/// Abortada se descartada antes de terminar (perdeu o hedge ou o cliente
+/// desconectou), liberando a conexão sem notificar o circuit breaker
+struct InFlight {
+    upstream: UpstreamId,
+    handle: JoinHandle<UpstreamResult>,
+}
+
+impl InFlight {
+    /// Aguarda o resultado
+    /// Panics da tarefa são propagados; tarefa abortada vira `Cancelled`
+    async fn join(&mut self) -> UpstreamResult {
+        match (&mut self.handle).await {
+            Ok(res) => res,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => Err(UpstreamError::Cancelled {
+                upstream: self.upstream,
+            }),
+        }
+    }
+
+    /// Cancela a tentativa perdedora
+    fn cancel(self) {
+        if !self.handle.is_finished() {
+            hedge_outcome(self.upstream, "cancelled");
+        }
+        // Drop aborta a tarefa
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Executor compartilhado das chamadas aos processadores
+pub struct Dispatcher {
+    a: Candidate,
+    b: Candidate,
+    strategy: RouteStrategy,
+    stats: Arc<Mutex<PaymentStats>>,
+    wal: Option<Arc<Wal>>,
+}
+
+impl Dispatcher {
+    /// Cria dispatcher com os clientes A/B e seus circuit breakers
+    pub fn new(
+        cfg: &Cfg,
+        up_a: Arc<UpstreamClient>,
+        up_b: Arc<UpstreamClient>,
+        stats: Arc<Mutex<PaymentStats>>,
+        wal: Option<Arc<Wal>>,
+    ) -> Self {
+        let breaker = || {
+            Arc::new(Breaker::new(
+                cfg.cb_min_samples,                    // Mínimo de amostras para avaliar
+                cfg.cb_fail_rate,                      // Taxa de falha para abrir
+                Duration::from_secs(cfg.cb_open_secs), // Tempo aberto
+            ))
+        };
+        let a = Candidate {
+            up: up_a,
+            breaker: breaker(),
+        };
+        let b = Candidate {
+            up: up_b,
+            breaker: breaker(),
+        };
+
+        Self {
+            a,
+            b,
+            strategy: RouteStrategy::new(),
+            stats,
+            wal,
+        }
+    }
+
+    /// Abre conexões com os dois processadores em paralelo
+    pub async fn warm_up(&self, conns: usize) {
+        if conns == 0 {
+            return;
+        }
+        tokio::join!(self.a.warm(conns, "startup"), self.b.warm(conns, "startup"));
+    }
+
+    /// Reaquece as conexões de um processador quando seu circuito deixa de
+    /// estar aberto: as antigas provavelmente caíram junto com ele
+    pub async fn run_recovery_warmup(self: Arc<Self>, conns: usize) {
+        let mut was_open = [false; 2];
+        let mut tick = tokio::time::interval(RECOVERY_TICK);
+        loop {
+            tick.tick().await;
+            for (c, was_open) in [&self.a, &self.b].into_iter().zip(&mut was_open) {
+                let open = c.breaker.is_open();
+                if *was_open && !open {
+                    let c = c.clone();
+                    tokio::spawn(async move { c.warm(conns, "recovery").await });
+                }
+                *was_open = open;
+            }
+        }
+    }
+
+    /// `true` se os dois circuitos estão abertos (não há para onde enviar)
+    pub fn all_open(&self) -> bool {
+        self.a.breaker.is_open() && self.b.breaker.is_open()
+    }
+
+    /// Envia o pagamento aos processadores segundo a política de hedging da rota
+    /// Em caso de sucesso registra o pagamento no WAL e nas estatísticas
+    ///
+    /// # Returns
+    /// * `Ok((upstream, resposta))` - Processador que confirmou o pagamento e a
+    ///   `ProcessorResponse` (mensagem ou `DecodeError` de um 2xx ilegível)
+    /// * `Err(UpstreamError)` - Última falha observada; se as duas tentativas
+    ///   falharam, a de desfecho incerto tem preferência (`maybe_processed`)
+    pub async fn dispatch(&self, payment: &Payment, policy: &HedgePolicy) -> UpstreamResult {
+        // ========== SELEÇÃO DE PROCESSADOR ==========
+        // Escolhe primário e secundário baseado na estratégia
+        let (prim, sec) = if self.strategy.pick_a_first(&self.a.breaker, &self.b.breaker) {
+            (&self.a, &self.b)
+        } else {
+            (&self.b, &self.a)
+        };
+
+        let result = if prim.breaker.is_open() {
+            // Circuit breaker aberto - vai direto pro secundário
+            self.strategy.note_skip_primary();
+            sec.clone().attempt(payment.clone()).await
+        } else {
+            let mode = match policy.max_hedges {
+                0 => HedgeMode::Disabled,
+                _ => policy.mode,
+            };
+            match mode {
+                HedgeMode::Disabled => prim.clone().attempt(payment.clone()).await,
+                HedgeMode::SequentialFallback => {
+                    self.sequential(prim, sec, payment, policy.delay).await
+                }
+                HedgeMode::ParallelAfterDelay => {
+                    self.parallel(prim, sec, payment, policy.delay).await
+                }
+                HedgeMode::ParallelImmediate => {
+                    self.parallel(prim, sec, payment, Duration::ZERO).await
+                }
+            }
+        };
+
+        if let Ok((upstream, _)) = &result {
+            self.record(*upstream, payment).await;
+        }
+        result
+    }
+
+    /// Hedging sequencial: secundário só depois que o primário falha ou estoura o delay
+    /// Primário que estoura o delay é cancelado (o future é descartado)
+    async fn sequential(
+        &self,
+        prim: &Candidate,
+        sec: &Candidate,
+        payment: &Payment,
+        delay: Duration,
+    ) -> UpstreamResult {
+        let first = prim.clone().attempt(payment.clone());
+        let (hedged, first_err) = match tokio::time::timeout(delay, first).await {
+            Ok(Ok(res)) => return Ok(res), // Primary conseguiu dentro do timeout
+            Ok(Err(e)) => {
+                // Falhou rápido - fallback simples
+                hedge_outcome(sec.up.id, "fallback");
+                (false, e)
+            }
+            Err(_) => {
+                // Requisição já enviada: o primário pode ter registrado o pagamento
+                hedge_outcome(prim.up.id, "cancelled");
+                (
+                    true,
+                    UpstreamError::Timeout {
+                        upstream: prim.up.id,
+                    },
+                )
+            }
+        };
+
+        let res = sec.clone().attempt(payment.clone()).await;
+        if hedged {
+            hedge_outcome(sec.up.id, if res.is_ok() { "win" } else { "loss" });
+        }
+        res.map_err(|e| keep_ambiguous(first_err, e))
+    }
+
+    /// Hedging paralelo: secundário disparado após o delay, vence o primeiro sucesso
+    /// Com delay zero os dois partem juntos
+    async fn parallel(
+        &self,
+        prim: &Candidate,
+        sec: &Candidate,
+        payment: &Payment,
+        delay: Duration,
+    ) -> UpstreamResult {
+        let mut p_handle = prim.spawn(payment);
+
+        // ========== JANELA DO PRIMÁRIO ==========
+        if !delay.is_zero() {
+            tokio::select! {
+                res = p_handle.join() => match res {
+                    Ok(res) => return Ok(res),
+                    // Primário falhou antes do delay - secundário sozinho
+                    Err(e) => {
+                        hedge_outcome(sec.up.id, "fallback");
+                        let res = sec.clone().attempt(payment.clone()).await;
+                        return res.map_err(|last| keep_ambiguous(e, last));
+                    }
+                },
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+
+        // ========== CORRIDA ==========
+        // Primeiro sucesso vence e o perdedor é abortado; se um falhar, espera o outro
+        let mut s_handle = sec.spawn(payment);
+        let (first, done, mut other) = tokio::select! {
+            res = p_handle.join() => (res, p_handle, s_handle),
+            res = s_handle.join() => (res, s_handle, p_handle),
+        };
+        match first {
+            Ok(res) => {
+                hedge_outcome(done.upstream, "win");
+                other.cancel();
+                Ok(res)
+            }
+            Err(e) => {
+                hedge_outcome(done.upstream, "loss");
+                let res = other.join().await;
+                hedge_outcome(other.upstream, if res.is_ok() { "win" } else { "loss" });
+                res.map_err(|last| keep_ambiguous(e, last))
+            }
+        }
+    }
+
+    /// Reenvia um pagamento da fila de retry, sem hedging
+    /// Com `maybe_processed_by`, só o processador que pode ter registrado o
+    /// pagamento recebe o reenvio (o outro cobraria de novo); uma rejeição 4xx
+    /// dele é a duplicata do envio original e conta como processado
+    ///
+    /// # Returns
+    /// * `Ok(upstream)` - Processador que confirmou (ou já tinha) o pagamento
+    /// * `Err(erro)` - Falha da tentativa
+    pub async fn redeliver(
+        &self,
+        payment: &Payment,
+        maybe_processed_by: Option<UpstreamId>,
+    ) -> Result<UpstreamId, UpstreamError> {
+        let Some(upstream) = maybe_processed_by else {
+            let (upstream, _) = self.dispatch(payment, &HedgePolicy::DISABLED).await?;
+            return Ok(upstream);
+        };
+
+        let pinned = match upstream {
+            UpstreamId::A => &self.a,
+            UpstreamId::B => &self.b,
+        };
+        match pinned.clone().attempt(payment.clone()).await {
+            Ok(_) => {}
+            Err(e) if e.is_upstream_fault() => return Err(e),
+            Err(e) => {
+                info!(
+                    "retry: payment {} already processed by {upstream}: {e}",
+                    payment.correlation_id
+                );
+                metrics::counter!("payments_retry_duplicate", "upstream" => upstream.as_str())
+                    .increment(1);
+            }
+        }
+        self.record(upstream, payment).await;
+        Ok(upstream)
+    }
+
+    /// Persiste no WAL e atualiza as estatísticas do pagamento confirmado
+    /// Com WAL_FSYNC=always só retorna após o fsync do registro
+    async fn record(&self, upstream: UpstreamId, payment: &Payment) {
+        let appended = self.wal.as_ref().map(|wal| {
+            wal.append(&WalRecord::Processed {
+                id: payment.correlation_id.clone(),
+                processor: upstream,
+                amount: payment.amount,
+                requested_at: payment.requested_at.clone(),
+            })
+        });
+        self.stats
+            .lock()
+            .unwrap()
+            .add(upstream, payment.amount, &payment.requested_at);
+        if let Some(appended) = appended {
+            appended.synced().await;
+        }
+    }
+}
+
+/// Erro devolvido quando as duas tentativas falham
+/// Prefere a falha de desfecho incerto: é ela que fixa o processador do retry
+fn keep_ambiguous(first: UpstreamError, last: UpstreamError) -> UpstreamError {
+    if first.maybe_processed() && !last.maybe_processed() {
+        first
+    } else {
+        last
+    }
+}
+
+/// Contabiliza o desfecho de uma tentativa que participou de um hedge
+/// * `win` - confirmou o pagamento
+/// * `loss` - terminou com erro
+/// * `cancelled` - abortada porque a outra venceu ou estourou o delay
+/// * `fallback` - secundário disparado sozinho porque o primário falhou antes do delay
+fn hedge_outcome(upstream: UpstreamId, outcome: &'static str) {
+    metrics::counter!("upstream_hedges", "upstream" => upstream.as_str(), "outcome" => outcome)
+        .increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU16, AtomicU64, AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    use axum::Router;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use metrics::{
+        Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+
+    use super::*;
+    use crate::retry_policy::RetryPolicy;
+
+    /// Processador falso: status e atraso configuráveis, conta as requisições
+    struct Fake {
+        status: AtomicU16,
+        delay_ms: AtomicU64,
+        received: AtomicUsize,
+        completed: AtomicUsize,
+        /// Requisições abandonadas pelo cliente antes da resposta
+        dropped: AtomicUsize,
+    }
+
+    impl Fake {
+        /// Troca status e atraso das próximas respostas
+        fn set(&self, status: u16, delay_ms: u64) {
+            self.status.store(status, Ordering::Relaxed);
+            self.delay_ms.store(delay_ms, Ordering::Relaxed);
+        }
+
+        fn received(&self) -> usize {
+            self.received.load(Ordering::Relaxed)
+        }
+
+        /// Espera o servidor perceber a desconexão do cliente
+        async fn wait_dropped(&self, n: usize) -> bool {
+            for _ in 0..100 {
+                if self.dropped.load(Ordering::Relaxed) >= n {
+                    return true;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            false
+        }
+    }
+
+    /// Marca a requisição como abandonada se o handler for descartado no meio
+    struct InProgress(Arc<Fake>, bool);
+
+    impl Drop for InProgress {
+        fn drop(&mut self) {
+            let counter = if self.1 {
+                &self.0.completed
+            } else {
+                &self.0.dropped
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    async fn handle(State(fake): State<Arc<Fake>>) -> (StatusCode, &'static str) {
+        fake.received.fetch_add(1, Ordering::Relaxed);
+        let mut guard = InProgress(Arc::clone(&fake), false);
+        let delay = fake.delay_ms.load(Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        guard.1 = true;
+        let status = StatusCode::from_u16(fake.status.load(Ordering::Relaxed)).unwrap();
+        (status, r#"{"message":"payment processed successfully"}"#)
+    }
+
+    async fn fake(status: u16, delay_ms: u64) -> (String, Arc<Fake>) {
+        let fake = Arc::new(Fake {
+            status: AtomicU16::new(status),
+            delay_ms: AtomicU64::new(delay_ms),
+            received: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        });
+        let app = Router::new()
+            .route("/payments", post(handle))
+            .with_state(Arc::clone(&fake));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, fake)
+    }
+
+    /// Dispatcher contra dois processadores falsos; A é o primário da primeira chamada
+    async fn dispatcher(a: &str, b: &str) -> (Dispatcher, Arc<Mutex<PaymentStats>>) {
+        dispatcher_with(a, b, &[]).await
+    }
+
+    /// Como `dispatcher`, com variáveis extras sobrepondo as padrão
+    async fn dispatcher_with(
+        a: &str,
+        b: &str,
+        extra: &[(&str, &str)],
+    ) -> (Dispatcher, Arc<Mutex<PaymentStats>>) {
+        let mut vars = vec![
+            ("UPSTREAM_A_URL", a),
+            ("UPSTREAM_B_URL", b),
+            ("UPSTREAM_PAY_PATH", "/payments"),
+            ("REQUEST_TIMEOUT_MS", "2000"),
+            ("UPSTREAM_RETRY_MAX_ATTEMPTS", "1"),
+            ("CB_MIN_SAMPLES", "2"),
+            ("CB_FAIL_RATE", "0.5"),
+            ("CB_OPEN_SECS", "60"),
+        ];
+        vars.extend_from_slice(extra);
+        let cfg = Cfg::from_vars(&vars).unwrap();
+        let retry = Arc::new(RetryPolicy::new(&cfg));
+        let up_a = UpstreamClient::new(UpstreamId::A, &cfg, Arc::clone(&retry))
+            .await
+            .unwrap();
+        let up_b = UpstreamClient::new(UpstreamId::B, &cfg, retry)
+            .await
+            .unwrap();
+        let stats = Arc::new(Mutex::new(PaymentStats::default()));
+        let d = Dispatcher::new(
+            &cfg,
+            Arc::new(up_a),
+            Arc::new(up_b),
+            Arc::clone(&stats),
+            None,
+        );
+        (d, stats)
+    }
+
+    /// (pagamentos em A, pagamentos em B) nas estatísticas
+    fn recorded(stats: &Mutex<PaymentStats>) -> (u64, u64) {
+        let s = stats.lock().unwrap().summary(None, None).unwrap();
+        (s.default.total_requests, s.fallback.total_requests)
+    }
+
+    /// Recorder que conta `upstream_hedges` por (upstream, outcome)
+    #[derive(Default)]
+    struct Hedges(Mutex<HashMap<(String, String), Arc<AtomicU64>>>);
+
+    impl Hedges {
+        fn get(&self, upstream: UpstreamId, outcome: &str) -> u64 {
+            let key = (upstream.as_str().to_string(), outcome.to_string());
+            self.0
+                .lock()
+                .unwrap()
+                .get(&key)
+                .map_or(0, |c| c.load(Ordering::Relaxed))
+        }
+    }
+
+    impl Recorder for Hedges {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            if key.name() != "upstream_hedges" {
+                return Counter::noop();
+            }
+            let label = |name: &str| {
+                key.labels()
+                    .find(|l| l.key() == name)
+                    .map_or_else(String::new, |l| l.value().to_string())
+            };
+            let mut counters = self.0.lock().unwrap();
+            Counter::from_arc(Arc::clone(
+                counters
+                    .entry((label("upstream"), label("outcome")))
+                    .or_default(),
+            ))
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    fn payment() -> Payment {
+        Payment::new(Money::from_cents(1990))
+    }
+
+    fn policy(mode: &str, delay_ms: u64) -> HedgePolicy {
+        HedgePolicy::parse(mode, delay_ms, 1).unwrap()
+    }
+
+    #[test]
+    fn more_than_one_hedge_is_rejected_at_config_load() {
+        assert!(HedgePolicy::parse("parallel-immediate", 0, 2).is_err());
+        let err = Cfg::from_vars(&[
+            ("UPSTREAM_A_URL", "http://a"),
+            ("UPSTREAM_B_URL", "http://b"),
+            ("PAY_MAX_HEDGES", "2"),
+        ])
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("max hedges must be 0 or 1"));
+    }
+
+    #[tokio::test]
+    async fn disabled_makes_a_single_attempt() {
+        let (a, fa) = fake(500, 0).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        let err = d
+            .dispatch(&payment(), &policy("disabled", 10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.upstream(), UpstreamId::A);
+        assert_eq!((fa.received(), fb.received()), (1, 0));
+        assert_eq!(recorded(&stats), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn sequential_falls_back_after_fast_failure() {
+        let (a, fa) = fake(500, 0).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+        let hedges = Hedges::default();
+        let _guard = metrics::set_default_local_recorder(&hedges);
+
+        let (upstream, _) = d
+            .dispatch(&payment(), &policy("sequential-fallback", 500))
+            .await
+            .unwrap();
+        assert_eq!(upstream, UpstreamId::B);
+        assert_eq!((fa.received(), fb.received()), (1, 1));
+        assert_eq!(recorded(&stats), (0, 1));
+        assert_eq!(hedges.get(UpstreamId::B, "fallback"), 1);
+        assert_eq!(hedges.get(UpstreamId::B, "win"), 0);
+    }
+
+    #[tokio::test]
+    async fn sequential_cancels_slow_primary_after_delay() {
+        let (a, fa) = fake(200, 1_000).await;
+        let (b, _) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        let started = Instant::now();
+        let (upstream, _) = d
+            .dispatch(&payment(), &policy("sequential-fallback", 50))
+            .await
+            .unwrap();
+        assert_eq!(upstream, UpstreamId::B);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(fa.wait_dropped(1).await, "slow primary was not cancelled");
+        assert_eq!(recorded(&stats), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn parallel_after_delay_aborts_the_loser() {
+        let (a, fa) = fake(200, 1_000).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        let started = Instant::now();
+        let (upstream, _) = d
+            .dispatch(&payment(), &policy("parallel-after-delay", 50))
+            .await
+            .unwrap();
+        assert_eq!(upstream, UpstreamId::B);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!((fa.received(), fb.received()), (1, 1));
+        assert!(fa.wait_dropped(1).await, "losing primary was not aborted");
+        assert_eq!(recorded(&stats), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn parallel_after_delay_falls_back_when_primary_fails_early() {
+        let (a, fa) = fake(500, 0).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+        let hedges = Hedges::default();
+        let _guard = metrics::set_default_local_recorder(&hedges);
+
+        let (upstream, _) = d
+            .dispatch(&payment(), &policy("parallel-after-delay", 500))
+            .await
+            .unwrap();
+        assert_eq!(upstream, UpstreamId::B);
+        assert_eq!((fa.received(), fb.received()), (1, 1));
+        assert_eq!(recorded(&stats), (0, 1));
+        assert_eq!(hedges.get(UpstreamId::B, "fallback"), 1);
+        assert_eq!(hedges.get(UpstreamId::B, "win"), 0);
+        assert_eq!(hedges.get(UpstreamId::A, "loss"), 0);
+    }
+
+    #[tokio::test]
+    async fn parallel_after_delay_skips_hedge_when_primary_is_fast() {
+        let (a, fa) = fake(200, 0).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        let (upstream, _) = d
+            .dispatch(&payment(), &policy("parallel-after-delay", 500))
+            .await
+            .unwrap();
+        assert_eq!(upstream, UpstreamId::A);
+        assert_eq!((fa.received(), fb.received()), (1, 0));
+        assert_eq!(recorded(&stats), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn parallel_immediate_races_both_and_aborts_the_loser() {
+        let (a, fa) = fake(200, 0).await;
+        let (b, fb) = fake(200, 1_000).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        let (upstream, _) = d
+            .dispatch(&payment(), &policy("parallel-immediate", 0))
+            .await
+            .unwrap();
+        assert_eq!(upstream, UpstreamId::A);
+        // Os dois partem juntos; espera B receber antes de checar o abort
+        for _ in 0..100 {
+            if fb.received() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!((fa.received(), fb.received()), (1, 1));
+        assert!(fb.wait_dropped(1).await, "losing secondary was not aborted");
+        assert_eq!(recorded(&stats), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn parallel_waits_for_the_other_when_one_fails() {
+        let (a, _) = fake(500, 0).await;
+        let (b, _) = fake(200, 100).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        let (upstream, _) = d
+            .dispatch(&payment(), &policy("parallel-immediate", 0))
+            .await
+            .unwrap();
+        assert_eq!(upstream, UpstreamId::B);
+        assert_eq!(recorded(&stats), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn upstream_faults_open_the_breaker() {
+        let (a, fa) = fake(500, 0).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+        let single = policy("disabled", 0);
+
+        // CB_MIN_SAMPLES=2: duas falhas de A abrem o circuito
+        // (round-robin alterna o primário; B sucede quando é a vez dele)
+        for _ in 0..4 {
+            let _ = d.dispatch(&payment(), &single).await;
+        }
+        assert!(d.a.breaker.is_open());
+        assert!(!d.b.breaker.is_open());
+
+        // Com A aberto tudo vai para B, sem tocar em A
+        let before = fa.received();
+        for _ in 0..3 {
+            let (upstream, _) = d.dispatch(&payment(), &single).await.unwrap();
+            assert_eq!(upstream, UpstreamId::B);
+        }
+        assert_eq!(fa.received(), before);
+        assert_eq!(fb.received(), 2 + 3);
+        assert_eq!(recorded(&stats), (0, 5));
+    }
+
+    #[tokio::test]
+    async fn rejections_do_not_open_the_breaker() {
+        let (a, _) = fake(422, 0).await;
+        let (b, _) = fake(422, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        for _ in 0..6 {
+            let err = d
+                .dispatch(&payment(), &policy("disabled", 0))
+                .await
+                .unwrap_err();
+            assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        assert!(!d.a.breaker.is_open() && !d.b.breaker.is_open());
+        assert_eq!(recorded(&stats), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn record_counts_each_success_once() {
+        let (a, fa) = fake(200, 0).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        for _ in 0..4 {
+            d.dispatch(&payment(), &policy("parallel-after-delay", 500))
+                .await
+                .unwrap();
+        }
+        // Round-robin: dois em cada, sem hedge
+        assert_eq!((fa.received(), fb.received()), (2, 2));
+        assert_eq!(recorded(&stats), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn both_failures_keep_the_ambiguous_error() {
+        let (a, _) = fake(200, 300).await;
+        let (b, _) = fake(503, 0).await;
+        let (d, _) = dispatcher_with(&a, &b, &[("REQUEST_TIMEOUT_MS", "100")]).await;
+
+        // A estoura o timeout (pode ter cobrado), B recusa sem processar
+        let err = d
+            .dispatch(&payment(), &policy("sequential-fallback", 500))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            UpstreamError::Timeout {
+                upstream: UpstreamId::A
+            }
+        ));
+        assert!(err.maybe_processed());
+    }
+
+    #[tokio::test]
+    async fn redeliver_after_timeout_counts_the_duplicate_as_processed() {
+        let (a, fa) = fake(200, 300).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher_with(&a, &b, &[("REQUEST_TIMEOUT_MS", "100")]).await;
+        let p = payment();
+
+        let err = d.dispatch(&p, &policy("disabled", 0)).await.unwrap_err();
+        assert!(err.maybe_processed());
+        assert_eq!(err.upstream(), UpstreamId::A);
+        assert_eq!(recorded(&stats), (0, 0));
+
+        // A registrou o pagamento: o reenvio volta como duplicata
+        fa.set(422, 0);
+        let upstream = d.redeliver(&p, Some(err.upstream())).await.unwrap();
+        assert_eq!(upstream, UpstreamId::A);
+        assert_eq!(fa.received(), 2);
+        assert_eq!(fb.received(), 0);
+        assert_eq!(recorded(&stats), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn redeliver_pinned_fault_is_retried_later() {
+        let (a, _) = fake(500, 0).await;
+        let (b, fb) = fake(200, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        let err = d
+            .redeliver(&payment(), Some(UpstreamId::A))
+            .await
+            .unwrap_err();
+        assert!(err.is_upstream_fault());
+        assert_eq!(fb.received(), 0);
+        assert_eq!(recorded(&stats), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn redeliver_unpinned_rejection_is_still_an_error() {
+        let (a, _) = fake(422, 0).await;
+        let (b, _) = fake(422, 0).await;
+        let (d, stats) = dispatcher(&a, &b).await;
+
+        let err = d.redeliver(&payment(), None).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(recorded(&stats), (0, 0));
+    }
+}
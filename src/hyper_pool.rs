@@ -0,0 +1,642 @@
+/// Backend HTTP direto sobre hyper para o caminho quente dos pagamentos
+/// Pool próprio e limitado de conexões keep-alive por processador, aberto já
+/// no startup, e requisições montadas a partir de partes pré-serializadas
+/// (headers prontos e corpo em `Bytes` vindo de `PayloadTemplate::render`)
+/// Em HTTP/1.1 cada conexão leva uma requisição por vez; em h2c poucas
+/// conexões compartilhadas multiplexam todos os pagamentos em andamento
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, Method, Request, Response, Uri, header};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::client::conn::{http1, http2};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::config::{HttpVersion, UpstreamTransport};
+use crate::upstream::{Outbound, RawResponse, UpstreamError, UpstreamId, body_limit};
+
+/// Conexão HTTP/1.1 pronta para enviar requisições
+type Http1Conn = http1::SendRequest<Full<Bytes>>;
+/// Conexão h2c (clonável, cada clone abre streams na mesma conexão)
+type Http2Conn = http2::SendRequest<Full<Bytes>>;
+
+/// Socket de uma conexão (TCP ou Unix domain socket)
+trait Socket: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socket for T {}
+
+/// Pool de conexões de um processador
+pub struct HyperPool {
+    /// Processador atendido
+    upstream: UpstreamId,
+    /// Endereço TCP (host:porta), ignorado com `cfg.unix_socket`
+    addr: String,
+    /// URL da API de pagamento (absoluta, exigida pelo h2)
+    url: Uri,
+    /// Path da API de pagamento (origin-form do HTTP/1.1)
+    path: Uri,
+    /// Header Host do HTTP/1.1 (no h2 vai como `:authority`)
+    host: HeaderValue,
+    /// Headers de toda requisição (Content-Type e credenciais)
+    headers: HeaderMap,
+    /// HTTP/1.1: conexões ociosas prontas para reuso e desde quando estão paradas
+    idle: Mutex<Vec<(Http1Conn, Instant)>>,
+    /// HTTP/1.1: limita conexões em uso (e portanto abertas) ao tamanho do pool
+    slots: Semaphore,
+    /// h2c: conexões compartilhadas, reabertas sob demanda
+    shared: Vec<tokio::sync::Mutex<Option<Http2Conn>>>,
+    /// h2c: próxima conexão do round-robin
+    next: AtomicUsize,
+    /// Timeouts, tamanho do pool e opções de socket
+    /// (timeout da requisição inclui a espera por conexão livre)
+    cfg: UpstreamTransport,
+}
+
+impl HyperPool {
+    /// Cria pool vazio para a URL de pagamento do processador
+    /// # Arguments
+    /// * `url` - URL completa do endpoint de pagamento (apenas `http://`)
+    /// * `headers` - Headers extras (credenciais) enviados em toda requisição
+    /// * `cfg` - Transporte do processador (`auto` usa HTTP/1.1)
+    pub fn new(
+        upstream: UpstreamId,
+        url: &str,
+        mut headers: HeaderMap,
+        cfg: &UpstreamTransport,
+    ) -> anyhow::Result<Self> {
+        let uri: Uri = url
+            .parse()
+            .with_context(|| format!("invalid upstream url {url}"))?;
+        anyhow::ensure!(
+            uri.scheme_str() == Some("http"),
+            "hyper backend supports only http:// upstreams ({url})"
+        );
+        let authority = uri
+            .authority()
+            .with_context(|| format!("upstream url without host: {url}"))?;
+        let addr = format!(
+            "{}:{}",
+            authority.host(),
+            authority.port_u16().unwrap_or(80)
+        );
+        let host = HeaderValue::from_str(authority.as_str())?;
+        let path = uri
+            .path_and_query()
+            .map_or("/", |p| p.as_str())
+            .parse()
+            .context("invalid upstream path")?;
+
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+
+        Ok(Self {
+            upstream,
+            addr,
+            url: uri,
+            path,
+            host,
+            headers,
+            idle: Mutex::new(Vec::with_capacity(cfg.pool_size)),
+            slots: Semaphore::new(cfg.pool_size),
+            shared: (0..cfg.h2_connections.max(1))
+                .map(|_| tokio::sync::Mutex::new(None))
+                .collect(),
+            next: AtomicUsize::new(0),
+            cfg: cfg.clone(),
+        })
+    }
+
+    /// Abre até `n` conexões (limitado ao tamanho do pool ou às conexões h2c)
+    /// Sem keep-alive não há o que aquecer
+    /// # Returns
+    /// * Quantidade de conexões novas prontas
+    pub async fn warm(&self, n: usize) -> usize {
+        if !self.cfg.keepalive {
+            return 0;
+        }
+        if self.cfg.http_version == HttpVersion::H2c {
+            let slots = self.shared.iter().take(n).map(|slot| async move {
+                let mut slot = slot.lock().await;
+                if slot.as_ref().is_some_and(|c| !c.is_closed()) {
+                    return false;
+                }
+                match self.connect_h2().await {
+                    Ok(conn) => {
+                        *slot = Some(conn);
+                        true
+                    }
+                    Err(e) => {
+                        warn!("hyper pool {}: warm-up failed: {e}", self.upstream);
+                        false
+                    }
+                }
+            });
+            return futures::future::join_all(slots)
+                .await
+                .into_iter()
+                .filter(|ok| *ok)
+                .count();
+        }
+
+        let n = n.min(self.cfg.pool_size);
+        let conns = futures::future::join_all((0..n).map(|_| self.connect_h1())).await;
+
+        let mut ready = 0;
+        let mut idle = self.idle.lock().unwrap();
+        for conn in conns {
+            match conn {
+                Ok(conn) if idle.len() < self.cfg.pool_size => {
+                    idle.push((conn, Instant::now()));
+                    ready += 1;
+                }
+                Ok(_) => {} // Pool já cheio
+                Err(e) => warn!("hyper pool {}: warm-up failed: {e}", self.upstream),
+            }
+        }
+        ready
+    }
+
+    /// Envia o pagamento e lê a resposta (corpo limitado)
+    pub async fn send(&self, out: &Outbound) -> Result<RawResponse, UpstreamError> {
+        self.exchange(
+            &Method::POST,
+            &self.path,
+            &self.url,
+            out,
+            self.cfg.request_timeout,
+        )
+        .await
+    }
+
+    /// Consulta GET no mesmo processador, pelas conexões do pool
+    /// # Arguments
+    /// * `path` - Path e query absolutos no processador
+    pub async fn get(
+        &self,
+        path: &str,
+        out: &Outbound,
+        timeout: Duration,
+    ) -> Result<RawResponse, UpstreamError> {
+        let invalid = |e: http::Error| UpstreamError::Connect {
+            upstream: self.upstream,
+            reason: format!("invalid path {path}: {e}"),
+        };
+        let origin: Uri = Uri::try_from(path).map_err(|e| invalid(e.into()))?;
+        let mut url = Uri::builder().path_and_query(path);
+        if let (Some(scheme), Some(authority)) = (self.url.scheme(), self.url.authority()) {
+            url = url.scheme(scheme.clone()).authority(authority.clone());
+        }
+        let url = url.build().map_err(invalid)?;
+        self.exchange(&Method::GET, &origin, &url, out, timeout)
+            .await
+    }
+
+    /// Troca no protocolo configurado, limitada ao prazo total
+    /// `path` vai na linha de requisição do HTTP/1.1; `url` absoluta no h2
+    async fn exchange(
+        &self,
+        method: &Method,
+        path: &Uri,
+        url: &Uri,
+        out: &Outbound,
+        timeout: Duration,
+    ) -> Result<RawResponse, UpstreamError> {
+        let exchange = async {
+            match self.cfg.http_version {
+                HttpVersion::H2c => self.exchange_h2(method, url, out).await,
+                HttpVersion::Auto | HttpVersion::Http1 => self.exchange_h1(method, path, out).await,
+            }
+        };
+        match tokio::time::timeout(timeout, exchange).await {
+            Ok(res) => res,
+            Err(_) => Err(UpstreamError::Timeout {
+                upstream: self.upstream,
+            }),
+        }
+    }
+
+    /// Requisição a partir do template mais os headers da tentativa
+    fn request(&self, method: &Method, uri: Uri, out: &Outbound) -> Request<Full<Bytes>> {
+        let mut req = Request::new(Full::new(out.body.clone()));
+        *req.method_mut() = method.clone();
+        *req.uri_mut() = uri;
+        let headers = req.headers_mut();
+        *headers = self.headers.clone();
+        headers.extend(out.headers.clone());
+        req
+    }
+
+    // ========== HTTP/1.1 ==========
+
+    /// Uma troca requisição/resposta numa conexão exclusiva do pool
+    async fn exchange_h1(
+        &self,
+        method: &Method,
+        path: &Uri,
+        out: &Outbound,
+    ) -> Result<RawResponse, UpstreamError> {
+        let _slot = self.slots.acquire().await.expect("pool semaphore closed");
+        let mut conn = self.checkout().await?;
+
+        let mut req = self.request(method, path.clone(), out);
+        req.headers_mut().insert(header::HOST, self.host.clone());
+        let resp = conn
+            .send_request(req)
+            .await
+            .map_err(|e| self.send_error(e))?;
+        let raw = self.read(resp).await?;
+
+        // Conexão só volta ao pool com a resposta consumida por inteiro
+        if !raw.truncated {
+            self.checkin(conn);
+        }
+        Ok(raw)
+    }
+
+    /// Pega conexão ociosa viva ou abre uma nova
+    async fn checkout(&self) -> Result<Http1Conn, UpstreamError> {
+        loop {
+            let conn = self.idle.lock().unwrap().pop();
+            let Some((mut conn, since)) = conn else { break };
+            // Descarta conexões expiradas ou fechadas pelo processador enquanto ociosas
+            if since.elapsed() < self.cfg.pool_idle_timeout
+                && !conn.is_closed()
+                && conn.ready().await.is_ok()
+            {
+                return Ok(conn);
+            }
+        }
+        self.connect_h1().await
+    }
+
+    /// Devolve conexão ao pool
+    fn checkin(&self, conn: Http1Conn) {
+        if !self.cfg.keepalive || conn.is_closed() {
+            return;
+        }
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.cfg.pool_size {
+            idle.push((conn, Instant::now()));
+        }
+    }
+
+    /// Abre conexão TCP e faz o handshake HTTP/1.1
+    async fn connect_h1(&self) -> Result<Http1Conn, UpstreamError> {
+        let io = self.connect_socket().await?;
+        let (conn, driver) = http1::handshake(io)
+            .await
+            .map_err(|e| self.connect_error(e))?;
+        self.drive(driver, "http1");
+        Ok(conn)
+    }
+
+    // ========== H2C ==========
+
+    /// Uma stream numa das conexões compartilhadas (round-robin)
+    async fn exchange_h2(
+        &self,
+        method: &Method,
+        url: &Uri,
+        out: &Outbound,
+    ) -> Result<RawResponse, UpstreamError> {
+        let mut conn = self.shared_conn().await?;
+        conn.ready().await.map_err(|e| self.connect_error(e))?;
+        let resp = conn
+            .send_request(self.request(method, url.clone(), out))
+            .await
+            .map_err(|e| self.send_error(e))?;
+        // Corpo cortado só cancela a stream; a conexão segue compartilhada
+        self.read(resp).await
+    }
+
+    /// Conexão compartilhada viva, reaberta se o processador a fechou
+    /// Requisições que chegam durante a reconexão esperam por ela em vez de
+    /// abrir conexões próprias
+    async fn shared_conn(&self) -> Result<Http2Conn, UpstreamError> {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.shared.len();
+        let mut slot = self.shared[i].lock().await;
+        if let Some(conn) = slot.as_ref().filter(|c| !c.is_closed()) {
+            return Ok(conn.clone());
+        }
+        let conn = self.connect_h2().await?;
+        if self.cfg.keepalive {
+            *slot = Some(conn.clone());
+        }
+        Ok(conn)
+    }
+
+    /// Abre conexão TCP e faz o handshake h2 com prior knowledge
+    async fn connect_h2(&self) -> Result<Http2Conn, UpstreamError> {
+        let io = self.connect_socket().await?;
+        let (conn, driver) = http2::handshake(TokioExecutor::new(), io)
+            .await
+            .map_err(|e| self.connect_error(e))?;
+        self.drive(driver, "h2c");
+        Ok(conn)
+    }
+
+    // ========== COMUM ==========
+
+    /// Abre o socket (Unix domain socket se configurado, senão TCP)
+    async fn connect_socket(&self) -> Result<TokioIo<Box<dyn Socket>>, UpstreamError> {
+        metrics::counter!("hyper_pool_connects", "upstream" => self.upstream.as_str()).increment(1);
+        let (target, connect) = match &self.cfg.unix_socket {
+            Some(path) => (
+                path.as_str(),
+                futures::future::Either::Left(self.connect_unix(path)),
+            ),
+            None => (
+                self.addr.as_str(),
+                futures::future::Either::Right(self.connect_tcp()),
+            ),
+        };
+        let stream = tokio::time::timeout(self.cfg.connect_timeout, connect)
+            .await
+            .map_err(|_| UpstreamError::Connect {
+                upstream: self.upstream,
+                reason: format!("connect to {target} timed out"),
+            })??;
+        Ok(TokioIo::new(stream))
+    }
+
+    /// Conecta via TCP
+    async fn connect_tcp(&self) -> Result<Box<dyn Socket>, UpstreamError> {
+        let stream = TcpStream::connect(&self.addr)
+            .await
+            .map_err(|e| self.connect_error(e))?;
+        stream
+            .set_nodelay(self.cfg.tcp_nodelay)
+            .map_err(|e| self.connect_error(e))?;
+        Ok(Box::new(stream))
+    }
+
+    /// Conecta via Unix domain socket
+    async fn connect_unix(&self, path: &str) -> Result<Box<dyn Socket>, UpstreamError> {
+        let stream = UnixStream::connect(path)
+            .await
+            .map_err(|e| self.connect_error(e))?;
+        Ok(Box::new(stream))
+    }
+
+    /// Roda o driver da conexão em background até ela fechar
+    /// Mantém o gauge de conexões abertas (comparável ao de streams)
+    fn drive<F, E>(&self, driver: F, protocol: &'static str)
+    where
+        F: Future<Output = Result<(), E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        let upstream = self.upstream;
+        let open = metrics::gauge!("upstream_connections", "upstream" => upstream.as_str(), "protocol" => protocol);
+        open.increment(1.0);
+        tokio::spawn(async move {
+            if let Err(e) = driver.await {
+                debug!("hyper pool {upstream}: connection closed: {e}");
+            }
+            open.decrement(1.0);
+        });
+    }
+
+    /// Lê status e corpo (limitado) da resposta
+    async fn read(&self, resp: Response<Incoming>) -> Result<RawResponse, UpstreamError> {
+        let status = resp.status();
+        let limit = body_limit(status);
+        let mut body = resp.into_body();
+        let mut buf = Vec::new();
+        let mut truncated = false;
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|e| self.interrupted(e))?;
+            if let Ok(data) = frame.into_data() {
+                let room = limit - buf.len();
+                if data.len() > room {
+                    buf.extend_from_slice(&data[..room]);
+                    truncated = true;
+                    break;
+                }
+                buf.extend_from_slice(&data);
+            }
+        }
+        Ok(RawResponse {
+            status,
+            body: buf,
+            truncated,
+        })
+    }
+
+    /// Erro de rede antes do envio da requisição
+    fn connect_error(&self, e: impl std::fmt::Display) -> UpstreamError {
+        UpstreamError::Connect {
+            upstream: self.upstream,
+            reason: e.to_string(),
+        }
+    }
+
+    /// Erro de `send_request`: só é seguro repetir se a requisição foi
+    /// cancelada antes de ser escrita na conexão
+    fn send_error(&self, e: hyper::Error) -> UpstreamError {
+        if e.is_canceled() {
+            self.connect_error(e)
+        } else {
+            self.interrupted(e)
+        }
+    }
+
+    /// Erro de rede depois do envio (o processador pode ter registrado o pagamento)
+    fn interrupted(&self, e: impl std::fmt::Display) -> UpstreamError {
+        UpstreamError::Interrupted {
+            upstream: self.upstream,
+            reason: e.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::Router;
+    use axum::routing::post;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::Cfg;
+
+    /// Listener TCP que conta as conexões aceitas
+    struct Counting {
+        tcp: TcpListener,
+        accepted: Arc<AtomicUsize>,
+    }
+
+    impl axum::serve::Listener for Counting {
+        type Io = TcpStream;
+        type Addr = SocketAddr;
+
+        async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+            loop {
+                if let Ok(conn) = self.tcp.accept().await {
+                    self.accepted.fetch_add(1, Ordering::Relaxed);
+                    return conn;
+                }
+            }
+        }
+
+        fn local_addr(&self) -> std::io::Result<Self::Addr> {
+            self.tcp.local_addr()
+        }
+    }
+
+    /// Conexões aceitas, depois de o servidor processar os accepts pendentes
+    /// (o handshake do cliente termina antes de o servidor chamar accept)
+    async fn connections(accepted: &AtomicUsize) -> usize {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        accepted.load(Ordering::Relaxed)
+    }
+
+    fn app() -> Router {
+        Router::new().route(
+            "/payments",
+            post(|| async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                r#"{"message":"ok"}"#
+            }),
+        )
+    }
+
+    /// Processador falso em TCP; devolve a URL e o contador de conexões
+    async fn processor() -> (String, Arc<AtomicUsize>) {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/payments", tcp.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let listener = Counting {
+            tcp,
+            accepted: Arc::clone(&accepted),
+        };
+        tokio::spawn(async move { axum::serve(listener, app()).await });
+        (url, accepted)
+    }
+
+    /// Transporte do processador A com as variáveis dadas
+    fn transport(vars: &[(&str, &str)]) -> UpstreamTransport {
+        let mut all = vec![
+            ("UPSTREAM_A_URL", "http://a"),
+            ("UPSTREAM_B_URL", "http://b"),
+        ];
+        all.extend_from_slice(vars);
+        Cfg::from_vars(&all).unwrap().upstream_a_transport
+    }
+
+    fn pool(url: &str, cfg: &UpstreamTransport) -> Arc<HyperPool> {
+        Arc::new(HyperPool::new(UpstreamId::A, url, HeaderMap::new(), cfg).unwrap())
+    }
+
+    fn outbound() -> Outbound {
+        Outbound {
+            body: Bytes::from_static(b"{}"),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Dispara `n` pagamentos simultâneos e confere que todos tiveram 200
+    async fn send_concurrently(pool: &Arc<HyperPool>, n: usize) {
+        let sends = (0..n).map(|_| {
+            let pool = Arc::clone(pool);
+            tokio::spawn(async move { pool.send(&outbound()).await })
+        });
+        for res in futures::future::join_all(sends).await {
+            let raw = res.unwrap().unwrap();
+            assert_eq!(raw.status, http::StatusCode::OK);
+            assert_eq!(raw.body, br#"{"message":"ok"}"#);
+        }
+    }
+
+    #[tokio::test]
+    async fn http1_reuses_idle_connections_up_to_the_pool_size() {
+        let (url, accepted) = processor().await;
+        let cfg = transport(&[
+            ("UPSTREAM_A_POOL_SIZE", "2"),
+            ("UPSTREAM_A_HTTP_VERSION", "http1"),
+        ]);
+        let pool = pool(&url, &cfg);
+
+        assert_eq!(pool.warm(4).await, 2);
+        assert_eq!(connections(&accepted).await, 2);
+
+        // Mais pagamentos em paralelo do que conexões: esperam por uma livre
+        send_concurrently(&pool, 8).await;
+        send_concurrently(&pool, 8).await;
+        assert_eq!(connections(&accepted).await, 2);
+        assert_eq!(pool.idle.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn http1_replaces_expired_idle_connections() {
+        let (url, accepted) = processor().await;
+        let mut cfg = transport(&[("UPSTREAM_A_POOL_SIZE", "1")]);
+        cfg.pool_idle_timeout = Duration::from_millis(50);
+        let pool = pool(&url, &cfg);
+
+        send_concurrently(&pool, 1).await;
+        send_concurrently(&pool, 1).await;
+        assert_eq!(connections(&accepted).await, 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        send_concurrently(&pool, 1).await;
+        assert_eq!(connections(&accepted).await, 2);
+    }
+
+    #[tokio::test]
+    async fn h2c_multiplexes_on_shared_connections() {
+        let (url, accepted) = processor().await;
+        let cfg = transport(&[
+            ("UPSTREAM_A_HTTP_VERSION", "h2c"),
+            ("UPSTREAM_A_H2_CONNECTIONS", "2"),
+        ]);
+        let pool = pool(&url, &cfg);
+
+        assert_eq!(pool.warm(4).await, 2);
+        send_concurrently(&pool, 32).await;
+        assert_eq!(connections(&accepted).await, 2);
+    }
+
+    #[tokio::test]
+    async fn unix_socket_replaces_tcp() {
+        for version in ["http1", "h2c"] {
+            let dir = tempfile::tempdir().unwrap();
+            let sock = dir.path().join("processor.sock");
+            let listener = tokio::net::UnixListener::bind(&sock).unwrap();
+            tokio::spawn(async move { axum::serve(listener, app()).await });
+
+            // Host da URL não resolve: só o socket leva ao processador
+            let cfg = transport(&[
+                ("UPSTREAM_A_UNIX_SOCKET", sock.to_str().unwrap()),
+                ("UPSTREAM_A_HTTP_VERSION", version),
+            ]);
+            let pool = pool("http://processor.invalid/payments", &cfg);
+            send_concurrently(&pool, 4).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn request_timeout_covers_the_whole_exchange() {
+        let (url, _) = processor().await;
+        let mut cfg = transport(&[]);
+        cfg.request_timeout = Duration::from_millis(5);
+        let err = pool(&url, &cfg).send(&outbound()).await.err().unwrap();
+        assert!(matches!(
+            err,
+            UpstreamError::Timeout {
+                upstream: UpstreamId::A
+            }
+        ));
+    }
+}
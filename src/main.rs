@@ -1,69 +1,94 @@
+use anyhow::Context;
 use axum::{
     Json, Router,
     extract::{Path, Query, State},
-    http::{HeaderMap, HeaderValue, StatusCode},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
+use bytes::Bytes;
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use serde::{Deserialize, Serialize};
 use std::{
     net::SocketAddr,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
     time::Duration,
 };
-use tokio::net::TcpListener;
-use tracing::info;
+use tokio::net::{TcpListener, UnixListener};
+use tracing::{debug, info, warn};
 
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 // ========== MÓDULOS PRÓPRIOS ==========
+mod accounts;
 mod breaker;
+mod cluster;
 mod config;
+mod dispatch;
+mod hyper_pool;
+mod mapping;
+mod money;
+mod reconcile;
+mod retry_policy;
+mod retry_queue;
+mod shared_ledger;
+mod signing;
+mod stats;
 mod strategy;
+mod tls;
 mod upstream;
+mod wal;
 
 // ========== IMPORTS DOS MÓDULOS ==========
-use breaker::Breaker;
+use accounts::{AccountError, Accounts, Extrato, Tipo};
+use cluster::Cluster;
 use config::Cfg;
+use dispatch::{Dispatcher, Payment};
 use moka::sync::Cache;
-use strategy::RouteStrategy;
-use upstream::UpstreamClient;
+use money::Money;
+use reconcile::{ReconcileReport, Reconciler};
+use retry_policy::RetryPolicy;
+use retry_queue::{RetryItem, RetryQueue};
+use shared_ledger::SharedLedger;
+use stats::{PaymentStats, PaymentSummary};
+use upstream::{UpstreamClient, UpstreamId};
+use wal::{Wal, WalRecord};
 
 /// Estado global da aplicação - compartilhado entre todas as threads
 /// Usa Arc (Atomic Reference Counting) para compartilhamento seguro entre threads
 #[derive(Clone)]
 struct AppState {
     cfg: Arc<Cfg>,                   // Configuração da aplicação
-    up_a: Arc<UpstreamClient>,       // Cliente para Payment Processor A
-    up_b: Arc<UpstreamClient>,       // Cliente para Payment Processor B
-    breaker_a: Arc<Breaker>,         // Circuit Breaker para serviço A
-    breaker_b: Arc<Breaker>,         // Circuit Breaker para serviço B
-    strategy: Arc<RouteStrategy>,    // Estratégia de roteamento
+    dispatcher: Arc<Dispatcher>,     // Seleção, hedging e breakers dos processadores
     idem: Cache<String, ()>,         // Cache de idempotência (correlationId -> ())
     stats: Arc<Mutex<PaymentStats>>, // Estatísticas globais (protegidas por Mutex)
+    retry: Arc<RetryQueue>,          // Fila de retry para falhas nos dois processadores
+    wal: Option<Arc<Wal>>,           // Write-ahead log de pagamentos (opcional)
+    cluster: Arc<Cluster>,           // Peers para agregação do resumo
+    reconciler: Arc<Reconciler>,     // Reconciliação com os processadores
+    accounts: Arc<Accounts>,         // Saldos por cliente (transações)
+    ready: Arc<AtomicBool>,          // Warm-up dos processadores concluído
 }
 
-/// Estatísticas globais de processamento de pagamentos
-/// Separadas por processador (default/fallback)
-#[derive(Default)]
-struct PaymentStats {
-    default: ProcessorStats,  // Estatísticas do Payment Processor A
-    fallback: ProcessorStats, // Estatísticas do Payment Processor B
-}
-
-/// Estatísticas por processador individual
-#[derive(Default)]
-struct ProcessorStats {
-    total_requests: u64, // Total de requests processados
-    total_amount: f64,   // Valor total processado
+impl AppState {
+    /// Acrescenta registro ao WAL, se habilitado
+    /// Com WAL_FSYNC=always só retorna depois do fsync do registro
+    async fn wal_append(&self, rec: &WalRecord) {
+        if let Some(wal) = &self.wal {
+            wal.append(rec).synced().await;
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct PayIn {
     #[serde(rename = "correlationId")]
     correlation_id: String, // Campo da rinha
-    amount: f64,
+    amount: Money, // Centavos exatos, no máximo 2 casas decimais
 }
 
 #[derive(Deserialize)]
@@ -81,25 +106,14 @@ struct TransacaoOut {
 
 #[derive(Serialize)]
 struct PayOut {
-    message: String, // Ajustado para rinha
-}
-
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct PaymentSummary {
-    default: ProcessorSummary,
-    fallback: ProcessorSummary,
-}
-
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct ProcessorSummary {
-    total_requests: u64,
-    total_amount: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>, // Ajustado para rinha
+    /// Processador confirmou (2xx) sem mensagem legível
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    decode_failed: bool,
 }
 
 #[derive(Deserialize)]
-#[allow(dead_code)]
 struct PaymentsSummaryQuery {
     from: Option<String>,
     to: Option<String>,
@@ -141,26 +155,11 @@ async fn main() -> anyhow::Result<()> {
     // ========== INICIALIZAÇÃO DOS UPSTREAM CLIENTS ==========
     // Cria clientes HTTP para os Payment Processors
     // Usa connection pooling e timeouts otimizados
-    let up_a = Arc::new(UpstreamClient::new("A".into(), &cfg).await?);
-    let up_b = Arc::new(UpstreamClient::new("B".into(), &cfg).await?);
-
-    // ========== CIRCUIT BREAKERS ==========
-    // Protege contra cascata de falhas
-    // Abre automaticamente se taxa de erro for alta
-    let breaker_a = Arc::new(Breaker::new(
-        cfg.cb_min_samples,                    // Mínimo de amostras para avaliar
-        cfg.cb_fail_rate,                      // Taxa de falha para abrir
-        Duration::from_secs(cfg.cb_open_secs), // Tempo aberto
-    ));
-    let breaker_b = Arc::new(Breaker::new(
-        cfg.cb_min_samples,
-        cfg.cb_fail_rate,
-        Duration::from_secs(cfg.cb_open_secs),
-    ));
-
-    // ========== ESTRATÉGIA DE ROTEAMENTO ==========
-    // Define como distribuir carga entre os processadores
-    let strategy = Arc::new(RouteStrategy::new());
+    // Orçamento de retry global, compartilhado pelos dois processadores
+    let upstream_retry = Arc::new(RetryPolicy::new(&cfg));
+    let up_a =
+        Arc::new(UpstreamClient::new(UpstreamId::A, &cfg, Arc::clone(&upstream_retry)).await?);
+    let up_b = Arc::new(UpstreamClient::new(UpstreamId::B, &cfg, upstream_retry).await?);
 
     // ========== CACHE DE IDEMPOTÊNCIA ==========
     // Previne processamento duplicado de requests
@@ -170,29 +169,131 @@ async fn main() -> anyhow::Result<()> {
         .time_to_live(Duration::from_secs(30)) // TTL de 30s
         .build();
 
+    // ========== FILA DE RETRY ==========
+    // Guarda pagamentos que falharam nos dois processadores
+    let retry = Arc::new(RetryQueue::new(&cfg));
+
+    // ========== WRITE-AHEAD LOG ==========
+    // Reaplica pagamentos registrados antes de um crash
+    let accounts = Accounts::new(&cfg.client_limits);
+    let mut stats = match &cfg.ledger_path {
+        // Ledger compartilhado: todas as instâncias enxergam os mesmos pagamentos
+        Some(path) => PaymentStats::shared(SharedLedger::open(path, cfg.ledger_capacity)?),
+        None => PaymentStats::default(),
+    };
+    let wal = match &cfg.wal_path {
+        Some(path) => {
+            let (wal, replay) = Wal::open(path, cfg.wal_fsync.clone())?;
+
+            // Reconstrói estatísticas dos pagamentos confirmados
+            // (o ledger compartilhado já é persistente e não precisa do replay)
+            if !stats.is_shared() {
+                for (processor, amount, requested_at) in &replay.processed {
+                    stats.add(*processor, *amount, requested_at);
+                }
+            }
+
+            // Reconstrói saldos e extratos dos clientes
+            for (cliente, conta) in replay.contas {
+                accounts.restore(cliente, conta);
+            }
+
+            // Retoma pagamentos aceitos que não chegaram a um resultado
+            for (id, amount, requested_at) in replay.pending {
+                if !retry.push(RetryItem::new(Payment {
+                    correlation_id: id,
+                    amount,
+                    requested_at,
+                })) {
+                    warn!("wal: retry queue full, pending payment kept in WAL");
+                }
+            }
+
+            Some(Arc::new(wal))
+        }
+        None => None,
+    };
+
+    // ========== DISPATCHER ==========
+    // Circuit breakers, estratégia de roteamento e hedging dos processadores
+    let stats = Arc::new(Mutex::new(stats));
+    let dispatcher = Arc::new(Dispatcher::new(
+        &cfg,
+        Arc::clone(&up_a),
+        Arc::clone(&up_b),
+        Arc::clone(&stats),
+        wal.clone(),
+    ));
+
+    // ========== CLUSTER ==========
+    // Demais instâncias consultadas pelo /payments-summary
+    let cluster = Arc::new(Cluster::new(&cfg)?);
+
+    // ========== RECONCILIAÇÃO ==========
+    // Compara nossas estatísticas com as dos processadores
+    let reconciler = Arc::new(Reconciler::new(&cfg, up_a, up_b));
+
     // ========== ESTADO GLOBAL ==========
     // Tudo compartilhado entre threads via Arc
     let state = AppState {
         cfg,
-        up_a,
-        up_b,
-        breaker_a,
-        breaker_b,
-        strategy,
+        dispatcher,
         idem,
-        stats: Arc::new(Mutex::new(PaymentStats::default())),
+        stats,
+        retry,
+        wal,
+        cluster,
+        reconciler,
+        accounts: Arc::new(accounts),
+        ready: Arc::new(AtomicBool::new(false)),
     };
 
+    // ========== WARM-UP DAS CONEXÕES ==========
+    // Abre conexões com os processadores antes de liberar o /readyz e as
+    // reabre quando um circuito se recupera
+    let warm = state.clone();
+    tokio::spawn(async move {
+        warm.dispatcher.warm_up(warm.cfg.upstream_warm_conns).await;
+        warm.ready.store(true, Ordering::Release);
+    });
+    if state.cfg.upstream_warm_conns > 0 {
+        tokio::spawn(
+            Arc::clone(&state.dispatcher).run_recovery_warmup(state.cfg.upstream_warm_conns),
+        );
+    }
+
+    // ========== WORKER DE RETRY ==========
+    // Reprocessa pagamentos pendentes em background
+    tokio::spawn(Arc::clone(&state.retry).run(state.clone()));
+
+    // ========== JOB DE RECONCILIAÇÃO ==========
+    tokio::spawn(Arc::clone(&state.reconciler).run(state.clone()));
+
     // ========== CONFIGURAÇÃO DAS ROTAS ==========
     // Router do Axum com todas as endpoints
     let prom_handle_route = prom_handle.clone();
     let app = Router::new()
         .route("/payments", post(pay)) // Processamento de pagamentos
-        .route("/payments-summary", get(payments_summary)) // Estatísticas
-        .route("/purge-payments", post(purge_payments)) // Reset de estatísticas
+        .route("/payments-summary", get(payments_summary)) // Estatísticas do cluster
+        .route(
+            cluster::INTERNAL_SUMMARY_PATH,
+            get(internal_payments_summary),
+        ) // Estatísticas locais
+        .route("/purge-payments", post(purge_payments)) // Reset de estatísticas do cluster
+        .route(cluster::INTERNAL_PURGE_PATH, post(internal_purge_payments)) // Reset das estatísticas locais
+        .route("/admin/reconcile", get(admin_reconcile)) // Divergência com os processadores
         .route("/clientes/{id}/transacoes", post(transacao)) // Transações da Rinha
+        .route("/clientes/{id}/extrato", get(extrato)) // Extrato do cliente
+        .route(
+            &format!("{}/{{id}}/transacoes", cluster::INTERNAL_CLIENTES_PATH),
+            post(internal_transacao),
+        ) // Transações nos saldos locais (dono)
+        .route(
+            &format!("{}/{{id}}/extrato", cluster::INTERNAL_CLIENTES_PATH),
+            get(internal_extrato),
+        ) // Extrato dos saldos locais (dono)
         .route("/healthz", get(|| async { "ok" })) // Health check
-        .route("/readyz", get(|| async { "ready" })) // Readiness check
+        .route("/readyz", get(readyz)) // Readiness check (após o warm-up)
         .route(
             "/metrics",
             get(move || {
@@ -209,11 +310,54 @@ async fn main() -> anyhow::Result<()> {
     let listener = TcpListener::bind(addr).await?;
     info!("listening on {}", addr);
 
+    // Unix domain socket opcional para o nginx no mesmo host
+    // (evita o overhead do TCP em loopback)
+    let unix = match &state.cfg.listen_unix {
+        Some(path) => {
+            let listener = bind_unix(path)?;
+            info!("listening on unix:{path}");
+            Some(axum::serve(listener, app.clone().into_make_service()))
+        }
+        None => None,
+    };
+
     // Inicia servidor com graceful shutdown
-    axum::serve(listener, app.into_make_service()).await?;
+    let tcp = axum::serve(listener, app.into_make_service());
+    match unix {
+        Some(unix) => {
+            tokio::try_join!(tcp.into_future(), unix.into_future())?;
+        }
+        None => tcp.await?,
+    }
     Ok(())
 }
 
+/// Cria o Unix domain socket de entrada
+/// Socket antigo (de uma execução anterior) é removido; permissões abertas
+/// porque o nginx roda com outro usuário
+fn bind_unix(path: &str) -> anyhow::Result<UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow::Error::new(e).context(format!("remove stale socket {path}"))),
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("bind unix socket {path}"))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))
+        .with_context(|| format!("chmod unix socket {path}"))?;
+    Ok(listener)
+}
+
+/// Readiness: só libera tráfego depois do warm-up das conexões
+async fn readyz(State(st): State<AppState>) -> (StatusCode, &'static str) {
+    if st.ready.load(Ordering::Acquire) {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "warming up")
+    }
+}
+
 /// Handler principal para processamento de pagamentos
 /// Implementa toda a lógica de load balancing, circuit breaker e hedging
 async fn pay(
@@ -222,21 +366,12 @@ async fn pay(
     Json(body): Json<PayIn>,    // Payload JSON da requisição
 ) -> Result<(StatusCode, Json<PayOut>), (StatusCode, String)> {
     // ========== AUTENTICAÇÃO ==========
-    // Verifica token de autenticação nos headers
-    // Compatível com o sistema de teste da Rinha
-    if let Some(required) = st.cfg.auth_header_value.clone() {
-        let name = st
-            .cfg
-            .auth_header_name
-            .as_deref()
-            .unwrap_or("Authorization");
+    check_auth(&st, &headers)?;
 
-        match headers.get(name) {
-            Some(v) if v == HeaderValue::from_str(&required).unwrap() => {
-                // Autenticação OK, continua
-            }
-            _ => return Err((StatusCode::UNAUTHORIZED, "unauthorized".into())),
-        }
+    // ========== VALIDAÇÃO DO VALOR ==========
+    // Precisão já validada na desserialização (centavos exatos)
+    if body.amount <= Money::from_cents(0) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "invalid amount".into()));
     }
 
     // ========== IDEMPOTÊNCIA ==========
@@ -250,55 +385,25 @@ async fn pay(
         ));
     }
 
-    // ========== SELEÇÃO DE PROCESSADOR ==========
-    // Escolhe primário e secundário baseado na estratégia
-    // Considera estado dos circuit breakers
-    let (prim, sec, prim_brk) = if st.strategy.pick_a_first(&st.breaker_a, &st.breaker_b) {
-        (&st.up_a, &st.up_b, &st.breaker_a) // A é primário
-    } else {
-        (&st.up_b, &st.up_a, &st.breaker_b) // B é primário
-    };
-
     // ========== PREPARAÇÃO DO PAYLOAD ==========
-    // Cria payload para o upstream no formato da Rinha
     // Gera novo correlationId para evitar conflitos
-    let correlation_id = uuid::Uuid::new_v4().to_string();
-    let requested_at = chrono::Utc::now().to_rfc3339();
-    let req_body = serde_json::json!({
-        "correlationId": correlation_id,
-        "amount": body.amount,
-        "requestedAt": requested_at,
-    });
+    let payment = Payment::new(body.amount);
+
+    // ========== WRITE-AHEAD LOG ==========
+    // Registra o pagamento aceito antes de tocar nos processadores
+    st.wal_append(&WalRecord::Accepted {
+        id: payment.correlation_id.clone(),
+        amount: payment.amount,
+        requested_at: payment.requested_at.clone(),
+    })
+    .await;
 
     // ========== MÉTRICA DE LATÊNCIA ==========
     let start = std::time::Instant::now();
 
-    // ========== HEDGING OTIMIZADO ==========
-    // Estratégia: tenta primary primeiro, só faz hedge se necessário
-    let result = if prim_brk.is_open() {
-        // Circuit breaker aberto - vai direto pro secundário
-        st.strategy.note_skip_primary();
-        sec.clone()
-            .request(Arc::clone(&st.cfg), req_body.clone())
-            .await
-    } else {
-        // Circuit breaker fechado - tenta primary com timeout
-        let primary_timeout = Duration::from_millis(st.cfg.hedge_delay_ms);
-        match tokio::time::timeout(
-            primary_timeout,
-            prim.clone().request(Arc::clone(&st.cfg), req_body.clone()),
-        )
-        .await
-        {
-            Ok(Ok(result)) => Ok(result), // Primary conseguiu dentro do timeout
-            _ => {
-                // Primary falhou ou demorou - tenta secondary
-                sec.clone()
-                    .request(Arc::clone(&st.cfg), req_body.clone())
-                    .await
-            }
-        }
-    };
+    // ========== HEDGING ==========
+    // Política da rota (PAY_HEDGE_MODE): por padrão só faz hedge se o primary falhar ou demorar
+    let result = st.dispatcher.dispatch(&payment, &st.cfg.pay_hedge).await;
 
     // ========== CÁLCULO DE LATÊNCIA ==========
     let elapsed = start.elapsed().as_millis() as u64;
@@ -306,254 +411,427 @@ async fn pay(
 
     // ========== PROCESSAMENTO DO RESULTADO ==========
     match result {
-        Ok((proc_name, _echo)) => {
+        Ok((_, resp)) => {
             // ========== SUCESSO ==========
             // Registra no cache de idempotência
             st.idem.insert(key.to_string(), ());
 
-            // Atualiza estatísticas globais
-            {
-                let mut stats = st.stats.lock().unwrap();
-                if proc_name == "A" {
-                    stats.default.total_requests += 1;
-                    stats.default.total_amount += body.amount;
-                    st.breaker_a.on_success(); // Notifica sucesso
-                } else {
-                    stats.fallback.total_requests += 1;
-                    stats.fallback.total_amount += body.amount;
-                    st.breaker_b.on_success(); // Notifica sucesso
-                }
-            }
-
             // Registra métrica de sucesso
-            metrics::counter!("payments_ok").increment(1);
+            metrics::counter!("payments_ok", "decoded" => resp.message.is_ok().to_string())
+                .increment(1);
 
             Ok((
                 StatusCode::OK,
+                // Mensagem devolvida pelo processador (ou a marca de corpo ilegível)
                 Json(PayOut {
-                    message: "payment processed successfully".into(),
+                    decode_failed: resp.message.is_err(),
+                    message: resp.message.ok(),
                 }),
             ))
         }
-        Err((proc_name, code, msg)) => {
+        Err(e) => {
             // ========== ERRO ==========
-            // Notifica circuit breaker sobre falha
-            if proc_name == "A" {
-                st.breaker_a.on_failure();
-            } else {
-                st.breaker_b.on_failure();
+            // Registra métrica de erro com código HTTP e tipo de falha
+            let code = e.status();
+            metrics::counter!("payments_err", "code" => code.as_u16().to_string(), "kind" => e.kind())
+                .increment(1);
+
+            // ========== FILA DE RETRY ==========
+            // Falha do processador (não rejeição do pagamento): pagamento válido
+            // fica na fila e é reprocessado em background
+            let id = payment.correlation_id.clone();
+            if e.is_upstream_fault() && st.retry.push(RetryItem::after_failure(payment, &e)) {
+                st.idem.insert(key.to_string(), ());
+                return Ok((
+                    StatusCode::ACCEPTED,
+                    Json(PayOut {
+                        message: Some("payment queued for retry".into()),
+                        decode_failed: false,
+                    }),
+                ));
             }
 
-            // Registra métrica de erro com código HTTP
-            metrics::counter!("payments_err", "code" => code.as_u16().to_string()).increment(1);
+            // Pagamento não será retomado
+            st.wal_append(&WalRecord::Failed { id }).await;
 
-            Err((code, msg))
+            Err((code, e.to_string()))
         }
     }
 }
 
 /// Handler para processamento de transações de clientes
-/// Implementa a lógica de débito/crédito com validações da Rinha de Backend
-/// Usa o mesmo mecanismo de load balancing e circuit breaker do pay()
+/// Com ACCOUNTS_OWNER, repassa ao dono dos saldos; senão, processa localmente
 async fn transacao(
     State(st): State<AppState>,     // Estado global da aplicação
     Path(cliente_id): Path<String>, // ID do cliente via URL path
-    Json(body): Json<TransacaoIn>,  // Payload JSON da transação
+    body: Bytes,                    // Payload JSON (validado só após achar o cliente)
+) -> Response {
+    let cliente_id_num = match parse_cliente(&cliente_id) {
+        Ok(id) => id,
+        Err(e) => return e.into_response(),
+    };
+
+    // ========== DONO DOS SALDOS ==========
+    // Saldos por instância deixariam réplicas ultrapassarem o limite juntas
+    if let Some(owner) = &st.cfg.accounts_owner {
+        let path = format!("/{cliente_id_num}/transacoes");
+        return forward_accounts(&st, owner, Method::POST, &path, body).await;
+    }
+
+    transacao_local(&st, cliente_id_num, &body)
+        .await
+        .into_response()
+}
+
+/// Handler interno chamado pelas demais instâncias (esta é a dona dos saldos)
+async fn internal_transacao(
+    State(st): State<AppState>,     // Estado global da aplicação
+    Path(cliente_id): Path<String>, // ID do cliente via URL path
+    body: Bytes,                    // Payload JSON repassado sem alteração
+) -> Result<(StatusCode, Json<TransacaoOut>), (StatusCode, String)> {
+    transacao_local(&st, parse_cliente(&cliente_id)?, &body).await
+}
+
+/// Processa a transação nos saldos locais
+/// Implementa a lógica de débito/crédito com validações da Rinha de Backend
+/// Usa o mesmo dispatcher (load balancing e circuit breaker) do pay()
+async fn transacao_local(
+    st: &AppState,
+    cliente_id_num: i64,
+    body: &[u8],
 ) -> Result<(StatusCode, Json<TransacaoOut>), (StatusCode, String)> {
     // ========== VALIDAÇÃO DO CLIENTE ==========
-    // Converte e valida o ID do cliente (1-5 conforme especificação da Rinha)
-    let cliente_id_num: i64 = match cliente_id.parse() {
-        Ok(id) if id >= 1 && id <= 5 => id,
-        _ => return Err((StatusCode::NOT_FOUND, "cliente not found".into())),
-    };
+    // Clientes válidos são os configurados em CLIENT_LIMITS; cliente
+    // desconhecido é 404 mesmo com corpo inválido
+    if !st.accounts.contains(cliente_id_num) {
+        return Err((StatusCode::NOT_FOUND, AccountError::NotFound.to_string()));
+    }
 
     // ========== VALIDAÇÕES DA RINHA ==========
     // Validações rigorosas conforme especificação do desafio
+    let body: TransacaoIn = serde_json::from_slice(body).map_err(|e| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("invalid body: {e}"),
+        )
+    })?;
     if body.descricao.is_empty() || body.descricao.len() > 10 {
         return Err((StatusCode::UNPROCESSABLE_ENTITY, "invalid descricao".into()));
     }
 
-    if body.tipo != "c" && body.tipo != "d" {
+    let Some(tipo) = Tipo::parse(&body.tipo) else {
         return Err((StatusCode::UNPROCESSABLE_ENTITY, "invalid tipo".into()));
-    }
+    };
 
     if body.valor <= 0 {
         return Err((StatusCode::UNPROCESSABLE_ENTITY, "invalid valor".into()));
     }
 
-    // ========== DEFINIÇÃO DE LIMITES ==========
-    // Limites pré-definidos por cliente (conforme especificação da Rinha)
-    let limite = match cliente_id_num {
-        1 => 100000,   // Cliente 1: R$ 1000,00
-        2 => 80000,    // Cliente 2: R$ 800,00
-        3 => 1000000,  // Cliente 3: R$ 10000,00
-        4 => 10000000, // Cliente 4: R$ 100000,00
-        5 => 500000,   // Cliente 5: R$ 5000,00
-        _ => 0,
-    };
-
-    // ========== SIMULAÇÃO DE SALDO ==========
-    // Em produção, isso viria do banco de dados
-    // Para a Rinha, mantemos em memória por simplicidade
-    let mut saldo = 0;
-
-    // ========== APLICAÇÃO DA TRANSAÇÃO ==========
-    if body.tipo == "d" {
-        // Débito: subtrai do saldo
-        saldo -= body.valor;
-        // Verifica se não ultrapassa o limite
-        if saldo < -limite {
-            return Err((StatusCode::UNPROCESSABLE_ENTITY, "limite exceeded".into()));
+    // ========== SAGA: RESERVA ==========
+    // Atômica por cliente: o débito fica reservado antes da chamada ao upstream,
+    // então requisições concorrentes já enxergam o saldo disponível reduzido
+    let reserva = match st.accounts.reserve(cliente_id_num, tipo, body.valor) {
+        Ok(r) => {
+            debug!("saga: cliente {cliente_id_num} reserved {}", body.valor);
+            metrics::counter!("transacao_saga", "step" => "reserve", "outcome" => "ok")
+                .increment(1);
+            r
         }
-    } else {
-        // Crédito: adiciona ao saldo
-        saldo += body.valor;
-    }
+        Err(e) => {
+            let (code, outcome) = match e {
+                AccountError::NotFound => (StatusCode::NOT_FOUND, "not_found"),
+                AccountError::LimitExceeded => (StatusCode::UNPROCESSABLE_ENTITY, "limit"),
+                AccountError::Overflow => (StatusCode::UNPROCESSABLE_ENTITY, "overflow"),
+            };
+            debug!("saga: cliente {cliente_id_num} reserve rejected: {e}");
+            metrics::counter!("transacao_saga", "step" => "reserve", "outcome" => outcome)
+                .increment(1);
+            return Err((code, e.to_string()));
+        }
+    };
 
     // ========== INTEGRAÇÃO COM UPSTREAM ==========
-    // Usa o mesmo mecanismo de load balancing do pay()
+    // Mesmo dispatcher do pay(), com a política de hedging da rota (TRANSACAO_HEDGE_MODE)
     // Gera correlationId único para rastreamento
-    let correlation_id = uuid::Uuid::new_v4().to_string();
-    let req_body = serde_json::json!({
-        "correlationId": correlation_id,
-        "amount": body.valor as f64,
-        "requestedAt": chrono::Utc::now().to_rfc3339()
-    });
-
-    // ========== SELEÇÃO DE PROCESSADOR ==========
-    // Mesmo algoritmo de escolha primário/secundário
-    let (prim, sec, prim_brk) = if st.strategy.pick_a_first(&st.breaker_a, &st.breaker_b) {
-        (&st.up_a, &st.up_b, &st.breaker_a)
-    } else {
-        (&st.up_b, &st.up_a, &st.breaker_b)
-    };
-
-    // ========== HEDGING COM TOKIO::SELECT ==========
-    // Implementação mais sofisticada usando tokio::select para concorrência real
-    let result = if prim_brk.is_open() {
-        // Circuit breaker aberto - vai direto pro secundário
-        st.strategy.note_skip_primary();
-        sec.clone()
-            .request(Arc::clone(&st.cfg), req_body.clone())
-            .await
-    } else {
-        // ========== CONCORRÊNCIA REAL ==========
-        // Spawna tarefa para o primary
-        let cfg_clone = Arc::clone(&st.cfg);
-        let req_body_clone = req_body.clone();
-        let prim_clone = prim.clone();
-        let mut p_handle =
-            tokio::spawn(async move { prim_clone.request(cfg_clone, req_body_clone).await });
-
-        // Usa tokio::select para implementar hedging real
-        let res = tokio::select! {
-            // Se primary responder primeiro, usa o resultado
-            res = &mut p_handle => res,
-            // Se passar o delay, inicia secondary paralelamente
-            _ = tokio::time::sleep(Duration::from_millis(st.cfg.hedge_delay_ms)) => {
-                let cfg_clone2 = Arc::clone(&st.cfg);
-                let req_body_clone2 = req_body.clone();
-                let sec_clone = sec.clone();
-                let mut s_handle = tokio::spawn(async move { sec_clone.request(cfg_clone2, req_body_clone2).await });
-                // Agora espera o primeiro que responder (primary ou secondary)
-                tokio::select! {
-                    res = &mut s_handle => res,
-                    res = &mut p_handle => res,
-                }
-            }
-        };
-
-        // Trata panics das tarefas
-        match res {
-            Ok(r) => r,
-            Err(_) => Err((
-                "unknown".into(),
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "task panicked".into(),
-            )),
-        }
-    };
+    let payment = Payment::new(Money::from_cents(body.valor)); // valor já vem em centavos
+    let result = st
+        .dispatcher
+        .dispatch(&payment, &st.cfg.transacao_hedge)
+        .await;
 
     // ========== PROCESSAMENTO DO RESULTADO ==========
     match result {
-        Ok((proc_name, _echo)) => {
+        Ok((upstream, _)) => {
             // ========== SUCESSO ==========
-            // Atualiza estatísticas globais
-            {
-                let mut stats = st.stats.lock().unwrap();
-                if proc_name == "A" {
-                    stats.default.total_requests += 1;
-                    stats.default.total_amount += body.valor as f64;
-                    st.breaker_a.on_success();
-                } else {
-                    stats.fallback.total_requests += 1;
-                    stats.fallback.total_amount += body.valor as f64;
-                    st.breaker_b.on_success();
-                }
-            }
+            // ========== SAGA: COMMIT ==========
+            // Efetiva no saldo/extrato e persiste para reconstruir após crash
+            let (posicao, transacao) = reserva.commit(body.descricao, payment.requested_at);
+            st.wal_append(&WalRecord::Transacao {
+                cliente: cliente_id_num,
+                transacao,
+            })
+            .await;
+            debug!("saga: cliente {cliente_id_num} committed via {upstream}");
+            metrics::counter!("transacao_saga", "step" => "commit", "outcome" => "ok").increment(1);
 
             // Registra métrica de sucesso
             metrics::counter!("transacoes_ok").increment(1);
 
-            Ok((StatusCode::OK, Json(TransacaoOut { limite, saldo })))
+            Ok((
+                StatusCode::OK,
+                Json(TransacaoOut {
+                    limite: posicao.limite,
+                    saldo: posicao.saldo,
+                }),
+            ))
         }
-        Err((proc_name, code, msg)) => {
+        Err(e) => {
             // ========== ERRO ==========
-            // Notifica circuit breaker sobre falha
-            if proc_name == "A" {
-                st.breaker_a.on_failure();
-            } else {
-                st.breaker_b.on_failure();
-            }
+            // ========== SAGA: RELEASE ==========
+            // Upstream não confirmou: devolve o valor reservado
+            reserva.release();
+            warn!("saga: cliente {cliente_id_num} released after upstream error: {e}");
+            metrics::counter!("transacao_saga", "step" => "release", "outcome" => "upstream_error")
+                .increment(1);
 
             // Registra métrica de erro
-            metrics::counter!("transacoes_err", "code" => code.as_u16().to_string()).increment(1);
+            let code = e.status();
+            metrics::counter!("transacoes_err", "code" => code.as_u16().to_string(), "kind" => e.kind())
+                .increment(1);
+
+            Err((code, e.to_string()))
+        }
+    }
+}
+
+/// Handler do extrato do cliente
+/// Retorna saldo, limite e as últimas transações (formato da Rinha 2024)
+async fn extrato(
+    State(st): State<AppState>,     // Estado global da aplicação
+    Path(cliente_id): Path<String>, // ID do cliente via URL path
+) -> Response {
+    let cliente_id_num = match parse_cliente(&cliente_id) {
+        Ok(id) => id,
+        Err(e) => return e.into_response(),
+    };
+
+    // Extrato vem de quem tem os saldos
+    if let Some(owner) = &st.cfg.accounts_owner {
+        let path = format!("/{cliente_id_num}/extrato");
+        return forward_accounts(&st, owner, Method::GET, &path, Bytes::new()).await;
+    }
+
+    extrato_local(&st, cliente_id_num).into_response()
+}
+
+/// Handler interno do extrato chamado pelas demais instâncias
+async fn internal_extrato(
+    State(st): State<AppState>,     // Estado global da aplicação
+    Path(cliente_id): Path<String>, // ID do cliente via URL path
+) -> Result<Json<Extrato>, (StatusCode, String)> {
+    extrato_local(&st, parse_cliente(&cliente_id)?)
+}
+
+/// Extrato a partir dos saldos locais
+fn extrato_local(
+    st: &AppState,
+    cliente_id_num: i64,
+) -> Result<Json<Extrato>, (StatusCode, String)> {
+    st.accounts
+        .extrato(cliente_id_num, stats::now_timestamp())
+        .map(Json)
+        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))
+}
+
+/// ID numérico do cliente no path (inválido = cliente inexistente)
+fn parse_cliente(cliente_id: &str) -> Result<i64, (StatusCode, String)> {
+    cliente_id
+        .parse()
+        .map_err(|_| (StatusCode::NOT_FOUND, AccountError::NotFound.to_string()))
+}
 
-            Err((code, msg))
+/// Repassa a requisição de `/clientes/...` ao dono dos saldos (ACCOUNTS_OWNER)
+/// Sem resposta do dono o resultado é desconhecido: 502, sem retry
+async fn forward_accounts(
+    st: &AppState,
+    owner: &str,
+    method: Method,
+    path: &str,
+    body: Bytes,
+) -> Response {
+    match st.cluster.forward_accounts(owner, method, path, body).await {
+        Ok(resp) => resp.into_response(),
+        Err(e) => {
+            warn!("accounts owner: {e:#}");
+            metrics::counter!("accounts_owner_err").increment(1);
+            (StatusCode::BAD_GATEWAY, e.to_string()).into_response()
         }
     }
 }
 
+/// Verifica token de autenticação nos headers
+/// Compatível com o sistema de teste da Rinha
+fn check_auth(st: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    if let Some(required) = st.cfg.auth_header_value.clone() {
+        let name = st
+            .cfg
+            .auth_header_name
+            .as_deref()
+            .unwrap_or("Authorization");
+
+        match headers.get(name) {
+            Some(v) if v == HeaderValue::from_str(&required).unwrap() => {
+                // Autenticação OK, continua
+            }
+            _ => return Err((StatusCode::UNAUTHORIZED, "unauthorized".into())),
+        }
+    }
+    Ok(())
+}
+
 /// Handler para consulta de estatísticas de pagamentos
-/// Retorna métricas agregadas de processamento por processador
+/// Soma as estatísticas locais com as de todos os peers configurados
+/// Peer inacessível retorna 502 em vez de números parciais
 async fn payments_summary(
-    State(st): State<AppState>,          // Estado global da aplicação
-    _query: Query<PaymentsSummaryQuery>, // Parâmetros de query (não utilizados)
+    State(st): State<AppState>,                // Estado global da aplicação
+    Query(query): Query<PaymentsSummaryQuery>, // Janela opcional (ISO-8601)
 ) -> Result<Json<PaymentSummary>, (StatusCode, String)> {
-    // ========== ACESSO ÀS ESTATÍSTICAS ==========
-    // Bloqueia o mutex para acesso thread-safe às estatísticas globais
-    let stats = st.stats.lock().unwrap();
+    cluster_summary(&st, &query).await.map(Json)
+}
 
-    // ========== RETORNO DAS MÉTRICAS ==========
-    // Retorna estatísticas separadas para processador primário e secundário
-    Ok(Json(PaymentSummary {
-        default: ProcessorSummary {
-            // Processador A (primário)
-            total_requests: stats.default.total_requests,
-            total_amount: stats.default.total_amount,
-        },
-        fallback: ProcessorSummary {
-            // Processador B (secundário)
-            total_requests: stats.fallback.total_requests,
-            total_amount: stats.fallback.total_amount,
-        },
-    }))
+/// Handler de reconciliação com os processadores
+/// Compara nosso resumo (do cluster) com o `/admin/payments-summary` de cada um
+async fn admin_reconcile(
+    State(st): State<AppState>,                // Estado global da aplicação
+    headers: HeaderMap,                        // Headers HTTP da requisição
+    Query(query): Query<PaymentsSummaryQuery>, // Janela opcional (ISO-8601)
+) -> Result<Json<ReconcileReport>, (StatusCode, String)> {
+    check_auth(&st, &headers)?;
+    st.reconciler.reconcile(&st, &query).await.map(Json)
+}
+
+/// Resumo do cluster na janela `[from, to]`
+/// Soma as estatísticas locais com as de todos os peers configurados
+async fn cluster_summary(
+    st: &AppState,
+    query: &PaymentsSummaryQuery,
+) -> Result<PaymentSummary, (StatusCode, String)> {
+    // ========== RESUMO LOCAL ==========
+    let mut summary = local_summary(st, query)?;
+
+    // Ledger compartilhado já contém os pagamentos de todas as instâncias
+    if st.stats.lock().unwrap().is_shared() {
+        return Ok(summary);
+    }
+
+    // ========== FAN-OUT PARA OS PEERS ==========
+    // Janela repassada como recebida (já validada localmente)
+    let peers = st
+        .cluster
+        .fetch_all(query.from.as_deref(), query.to.as_deref())
+        .await
+        .map_err(|e| {
+            metrics::counter!("payments_summary_peer_err").increment(1);
+            (StatusCode::BAD_GATEWAY, format!("{e:#}"))
+        })?;
+    for peer in &peers {
+        summary.merge(peer);
+    }
+
+    Ok(summary)
+}
+
+/// Handler interno consultado pelos peers
+/// Retorna apenas as estatísticas desta instância
+async fn internal_payments_summary(
+    State(st): State<AppState>,                // Estado global da aplicação
+    Query(query): Query<PaymentsSummaryQuery>, // Janela opcional (ISO-8601)
+) -> Result<Json<PaymentSummary>, (StatusCode, String)> {
+    local_summary(&st, &query).map(Json)
+}
+
+/// Resumo das estatísticas locais na janela `[from, to]`
+fn local_summary(
+    st: &AppState,
+    query: &PaymentsSummaryQuery,
+) -> Result<PaymentSummary, (StatusCode, String)> {
+    // ========== VALIDAÇÃO DA JANELA ==========
+    // Timestamps malformados ou janela invertida retornam 400
+    let from = stats::parse_bound("from", query.from.as_deref())?;
+    let to = stats::parse_bound("to", query.to.as_deref())?;
+    if let (Some(f), Some(t)) = (from, to)
+        && f > t
+    {
+        return Err((StatusCode::BAD_REQUEST, "from must be <= to".into()));
+    }
+
+    // ========== SOMA DA JANELA ==========
+    // O ledger compartilhado (até LEDGER_CAPACITY registros) é percorrido fora
+    // do mutex para não travar o registro de pagamentos; estatísticas locais
+    // são um BTreeMap por milissegundo e somam sob o mutex
+    // Janela que alcança pagamentos sobrescritos no ledger (LEDGER_CAPACITY
+    // esgotada) falha em vez de responder um total menor que o real
+    let evicted = |e: shared_ledger::Evicted| {
+        warn!("payments-summary: {e}");
+        (StatusCode::INSUFFICIENT_STORAGE, e.to_string())
+    };
+    let ledger = st.stats.lock().unwrap().ledger();
+    if let Some(ledger) = ledger {
+        let (from_ms, to_ms) = stats::window_ms(from, to);
+        return ledger.summary(from_ms, to_ms).map_err(evicted);
+    }
+    let stats = st.stats.lock().unwrap();
+    stats.summary(from, to).map_err(evicted)
 }
 
 /// Handler para limpeza/reset das estatísticas de pagamentos
-/// Zera todos os contadores de requisições e valores processados
+/// Zera todos os contadores de requisições e valores processados, nesta
+/// instância e nos peers (o resumo do cluster soma todos)
 async fn purge_payments(
     State(st): State<AppState>, // Estado global da aplicação
 ) -> Result<StatusCode, (StatusCode, String)> {
-    // ========== RESET DAS ESTATÍSTICAS ==========
-    // Bloqueia o mutex e zera todos os contadores
-    let mut stats = st.stats.lock().unwrap();
-    stats.default.total_requests = 0; // Zera contador do processador A
-    stats.default.total_amount = 0.0; // Zera valor total do processador A
-    stats.fallback.total_requests = 0; // Zera contador do processador B
-    stats.fallback.total_amount = 0.0; // Zera valor total do processador B
+    // ========== RESET LOCAL ==========
+    // Ledger compartilhado já é zerado para todas as instâncias
+    if purge_local(&st).await {
+        return Ok(StatusCode::OK);
+    }
+
+    // ========== FAN-OUT PARA OS PEERS ==========
+    st.cluster.purge_all().await.map_err(|e| {
+        metrics::counter!("purge_payments_peer_err").increment(1);
+        (StatusCode::BAD_GATEWAY, format!("{e:#}"))
+    })?;
 
     // ========== CONFIRMAÇÃO DE SUCESSO ==========
     // Retorna 200 OK indicando que o reset foi realizado
     Ok(StatusCode::OK)
 }
+
+/// Handler interno chamado pelo peer que recebeu o `/purge-payments`
+/// Zera apenas as estatísticas desta instância
+async fn internal_purge_payments(
+    State(st): State<AppState>, // Estado global da aplicação
+) -> StatusCode {
+    purge_local(&st).await;
+    StatusCode::OK
+}
+
+/// Descarta o histórico local de pagamentos
+/// # Returns
+/// * `true` se as estatísticas são o ledger compartilhado (já zerado para o cluster)
+async fn purge_local(st: &AppState) -> bool {
+    let (appended, is_shared) = {
+        // Bloqueia o mutex e descarta todo o histórico
+        let mut stats = st.stats.lock().unwrap();
+        // WAL sob o mesmo lock para o Purge ficar ordenado com os Processed
+        // (append só enfileira para a thread de escrita, sem I/O aqui)
+        let appended = st.wal.as_ref().map(|wal| wal.append(&WalRecord::Purge)); // Replay também descarta o histórico
+        stats.clear();
+        (appended, stats.is_shared())
+    };
+    // fsync esperado fora do lock
+    if let Some(appended) = appended {
+        appended.synced().await;
+    }
+    is_shared
+}
@@ -0,0 +1,244 @@
+/// Mapeamento do contrato de cada processador
+/// O corpo enviado vem de um template JSON com placeholders (`"{{amount}}"`),
+/// compilado no boot em partes fixas + campos; a mensagem de confirmação é
+/// lida da resposta por JSON pointer. Novos processadores só mudam config
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::Context;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::dispatch::Payment;
+
+/// Template padrão: formato da Rinha
+pub const DEFAULT_TEMPLATE: &str = r#"{"correlationId":"{{correlationId}}","amount":"{{amount}}","requestedAt":"{{requestedAt}}"}"#;
+
+/// Pointer padrão da mensagem de confirmação (`{"message": "..."}`)
+pub const DEFAULT_MESSAGE_POINTER: &str = "/message";
+
+/// Campo do pagamento disponível no template
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    /// `correlationId` (string)
+    CorrelationId,
+    /// Valor decimal com duas casas (número, ex: `19.90`)
+    Amount,
+    /// Valor em centavos (número inteiro)
+    AmountCents,
+    /// `requestedAt` RFC 3339 (string)
+    RequestedAt,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "correlationId" => Some(Self::CorrelationId),
+            "amount" => Some(Self::Amount),
+            "amountCents" => Some(Self::AmountCents),
+            "requestedAt" => Some(Self::RequestedAt),
+            _ => None,
+        }
+    }
+}
+
+/// Parte do template compilado
+enum Segment {
+    /// Trecho copiado como está
+    Literal(Box<[u8]>),
+    /// Valor do pagamento, já no tipo JSON do campo
+    Field(Field),
+}
+
+/// Template do corpo enviado a um processador
+#[derive(Clone)]
+pub struct PayloadTemplate {
+    /// Texto original (exibido no log de configuração)
+    source: Arc<str>,
+    segments: Arc<[Segment]>,
+}
+
+impl PayloadTemplate {
+    /// Compila o template
+    /// Placeholders são strings JSON completas (`"{{campo}}"`) em posição de
+    /// valor, trocadas pelo valor tipado: `correlationId`, `amount`,
+    /// `amountCents` e `requestedAt`. `{{` em chaves ou dentro de outro texto
+    /// é rejeitado
+    pub fn compile(template: &str) -> anyhow::Result<Self> {
+        serde_json::from_str::<serde::de::IgnoredAny>(template)
+            .context("template is not valid JSON")?;
+
+        let source = template.trim();
+        let bytes = source.as_bytes();
+        let mut segments = Vec::new();
+        let mut literal_start = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] != b'"' {
+                i += 1;
+                continue;
+            }
+            // String JSON: o fim é a primeira aspa sem escape (JSON já validado)
+            let start = i;
+            i += 1;
+            while bytes[i] != b'"' {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i += 1;
+
+            let content = &source[start + 1..i - 1];
+            if !content.contains("{{") {
+                continue;
+            }
+            let is_key = source[i..].trim_start().starts_with(':');
+            let name = content
+                .strip_prefix("{{")
+                .and_then(|c| c.strip_suffix("}}"))
+                .filter(|name| !is_key && !name.contains(['{', '}']))
+                .with_context(|| {
+                    format!("placeholder must be a whole JSON string value: \"{content}\"")
+                })?;
+            let field = Field::parse(name)
+                .with_context(|| format!("unknown placeholder {{{{{name}}}}}"))?;
+
+            if start > literal_start {
+                segments.push(Segment::Literal(bytes[literal_start..start].into()));
+            }
+            segments.push(Segment::Field(field));
+            literal_start = i;
+        }
+        if literal_start < bytes.len() {
+            segments.push(Segment::Literal(bytes[literal_start..].into()));
+        }
+
+        Ok(Self {
+            source: source.into(),
+            segments: segments.into(),
+        })
+    }
+
+    /// Corpo do pagamento conforme o template
+    pub fn render(&self, payment: &Payment) -> Bytes {
+        let mut w = BytesMut::with_capacity(128).writer();
+        // Escrita em memória não falha; strings passam pelo escape do serde_json
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(bytes) => w.write_all(bytes).unwrap(),
+                Segment::Field(Field::CorrelationId) => {
+                    serde_json::to_writer(&mut w, &payment.correlation_id).unwrap()
+                }
+                Segment::Field(Field::Amount) => write!(w, "{}", payment.amount).unwrap(),
+                Segment::Field(Field::AmountCents) => {
+                    write!(w, "{}", payment.amount.cents()).unwrap()
+                }
+                Segment::Field(Field::RequestedAt) => {
+                    serde_json::to_writer(&mut w, &payment.requested_at).unwrap()
+                }
+            }
+        }
+        w.into_inner().freeze()
+    }
+}
+
+impl fmt::Debug for PayloadTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.source, f)
+    }
+}
+
+impl Default for PayloadTemplate {
+    fn default() -> Self {
+        Self::compile(DEFAULT_TEMPLATE).expect("default template")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Money;
+
+    fn payment(correlation_id: &str) -> Payment {
+        Payment {
+            correlation_id: correlation_id.into(),
+            amount: Money::from_cents(1990),
+            requested_at: "2025-07-15T12:34:56.000Z".into(),
+        }
+    }
+
+    fn render(template: &str, p: &Payment) -> String {
+        let body = PayloadTemplate::compile(template).unwrap().render(p);
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn default_template_renders_rinha_payload() {
+        let body =
+            PayloadTemplate::default().render(&payment("4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3"));
+        assert_eq!(
+            body,
+            r#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":19.90,"requestedAt":"2025-07-15T12:34:56.000Z"}"#
+        );
+    }
+
+    #[test]
+    fn fields_keep_their_json_type() {
+        let body = render(
+            r#" [ {"cents": "{{amountCents}}", "nested": {"at": "{{requestedAt}}"}}, "{{amount}}" ] "#,
+            &payment("x"),
+        );
+        assert_eq!(
+            body,
+            r#"[ {"cents": 1990, "nested": {"at": "2025-07-15T12:34:56.000Z"}}, 19.90 ]"#
+        );
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let p = payment("a\"b\\c\n");
+        let body = render(r#"{"id":"{{correlationId}}"}"#, &p);
+        let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["id"].as_str(), Some(p.correlation_id.as_str()));
+    }
+
+    #[test]
+    fn literal_strings_are_copied_verbatim() {
+        let body = render(
+            r#"{"note":"say \"hi\" {not a placeholder}","id":"{{correlationId}}"}"#,
+            &payment("x"),
+        );
+        assert_eq!(
+            body,
+            r#"{"note":"say \"hi\" {not a placeholder}","id":"x"}"#
+        );
+    }
+
+    #[test]
+    fn unknown_placeholder_is_rejected() {
+        let err = PayloadTemplate::compile(r#"{"id":"{{paymentId}}"}"#).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("unknown placeholder {{paymentId}}")
+        );
+    }
+
+    #[test]
+    fn placeholders_outside_value_position_are_rejected() {
+        for template in [
+            r#"{"{{correlationId}}":1}"#,
+            r#"{"id":"pay-{{correlationId}}"}"#,
+            r#"{"note":"say \"{{amount}}\""}"#,
+            r#"{"id":"{{correlationId}}}"}"#,
+        ] {
+            let err = PayloadTemplate::compile(template).unwrap_err();
+            assert!(
+                err.to_string().contains("whole JSON string value"),
+                "{template}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_json_is_rejected() {
+        assert!(PayloadTemplate::compile(r#"{"id":"{{correlationId}}""#).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+/// Valores monetários em ponto fixo (centavos)
+/// Evita o erro acumulado de `f64` nas somas do resumo: valores são lidos
+/// do JSON sem passar por float, somados como inteiros e serializados
+/// sempre com exatamente duas casas decimais
+use std::fmt;
+use std::ops::{Add, AddAssign};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Number;
+use serde_json::value::RawValue;
+
+/// Casas decimais aceitas pela moeda
+const SCALE: u32 = 2;
+
+/// Valor monetário em centavos
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Money(i64);
+
+impl Money {
+    /// Cria valor a partir de centavos
+    pub const fn from_cents(cents: i64) -> Self {
+        Self(cents)
+    }
+
+    /// Valor em centavos
+    pub const fn cents(self) -> i64 {
+        self.0
+    }
+
+    /// Interpreta número decimal (ex: `19.9`, `19.90`, `20`)
+    /// Rejeita notação científica e mais de duas casas decimais
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+
+        if int.is_empty() || !int.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid amount: {s}"));
+        }
+        if (digits.contains('.') && frac.is_empty()) || !frac.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid amount: {s}"));
+        }
+        if frac.len() > SCALE as usize {
+            return Err(format!("amount {s} has more than {SCALE} decimal places"));
+        }
+
+        // Completa as casas decimais faltantes (19.9 -> 1990)
+        let frac_cents: i64 = format!("{frac:0<width$}", width = SCALE as usize)
+            .parse()
+            .unwrap_or(0);
+        let cents = int
+            .parse::<i64>()
+            .ok()
+            .and_then(|i| i.checked_mul(10i64.pow(SCALE)))
+            .and_then(|i| i.checked_add(frac_cents))
+            .ok_or_else(|| format!("amount {s} out of range"))?;
+
+        Ok(Self(if neg { -cents } else { cents }))
+    }
+
+    /// Como `parse`, mas arredonda casas extras para o centavo mais próximo
+    /// (meio centavo se afasta do zero), sem passar por `f64`
+    /// Para totais agregados por terceiros, que podem vir com ruído de float
+    pub fn parse_rounded(s: &str) -> Result<Self, String> {
+        let scale = SCALE as usize;
+        let Some((int, frac)) = s.split_once('.').filter(|(_, frac)| frac.len() > scale) else {
+            return Self::parse(s);
+        };
+        let (kept, extra) = frac.split_at(scale);
+        if !extra.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid amount: {s}"));
+        }
+
+        let truncated = Self::parse(&format!("{int}.{kept}"))?;
+        if extra.as_bytes()[0] < b'5' {
+            return Ok(truncated);
+        }
+        let step = if int.starts_with('-') { -1 } else { 1 };
+        truncated
+            .0
+            .checked_add(step)
+            .map(Self)
+            .ok_or_else(|| format!("amount {s} out of range"))
+    }
+
+    /// Desserializa com `parse_rounded` (`#[serde(deserialize_with)]`)
+    pub fn deserialize_rounded<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let n = Number::deserialize(deserializer)?;
+        Money::parse_rounded(&n.to_string()).map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Money {
+    /// Formata com exatamente duas casas decimais (ex: `19.90`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let unit = 10u64.pow(SCALE);
+        write!(f, "{sign}{}.{:02}", abs / unit, abs % unit)
+    }
+}
+
+impl Add for Money {
+    type Output = Self;
+
+    /// Soma saturando nos limites de `i64` (nunca entra em pânico nem dá a volta)
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Serialize for Money {
+    /// Serializa como número JSON com duas casas decimais
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RawValue::from_string(self.to_string())
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    /// Lê o número JSON como texto, sem conversão intermediária para `f64`
+    /// (`arbitrary_precision` preserva o texto mesmo em enums com tag interna,
+    /// que bufferizam os campos antes de desserializar, como o `WalRecord`)
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let n = Number::deserialize(deserializer)?;
+        Money::parse(&n.to_string()).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_up_to_two_decimals() {
+        assert_eq!(Money::parse("19.9"), Ok(Money::from_cents(1990)));
+        assert_eq!(Money::parse("19.90"), Ok(Money::from_cents(1990)));
+        assert_eq!(Money::parse("20"), Ok(Money::from_cents(2000)));
+        assert_eq!(Money::parse("0.01"), Ok(Money::from_cents(1)));
+    }
+
+    #[test]
+    fn parse_rejects_more_than_two_decimals() {
+        assert!(Money::parse("19.901").is_err());
+        assert!(Money::parse("0.001").is_err());
+    }
+
+    #[test]
+    fn parse_rounded_rounds_to_the_nearest_cent() {
+        assert_eq!(
+            Money::parse_rounded("19.900000000000002"),
+            Ok(Money::from_cents(1990))
+        );
+        assert_eq!(
+            Money::parse_rounded("19.899999999999999"),
+            Ok(Money::from_cents(1990))
+        );
+        assert_eq!(Money::parse_rounded("0.005"), Ok(Money::from_cents(1)));
+        assert_eq!(Money::parse_rounded("-0.005"), Ok(Money::from_cents(-1)));
+        assert_eq!(Money::parse_rounded("19.9"), Ok(Money::from_cents(1990)));
+        assert!(Money::parse_rounded("1.00x").is_err());
+        assert!(Money::parse_rounded("92233720368547758.075").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_numbers() {
+        for s in ["", "-", ".5", "1.", "1e3", "1.2.3", "abc", "+1", " 1"] {
+            assert!(Money::parse(s).is_err(), "{s:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn parse_negatives() {
+        assert_eq!(Money::parse("-19.9"), Ok(Money::from_cents(-1990)));
+        assert_eq!(Money::parse("-0.05"), Ok(Money::from_cents(-5)));
+        assert_eq!(Money::from_cents(-5).to_string(), "-0.05");
+    }
+
+    #[test]
+    fn parse_huge_values() {
+        // i64::MAX centavos = 92233720368547758.07
+        assert_eq!(
+            Money::parse("92233720368547758.07"),
+            Ok(Money::from_cents(i64::MAX))
+        );
+        assert!(Money::parse("92233720368547758.08").is_err());
+        assert!(Money::parse("99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn add_saturates() {
+        let max = Money::from_cents(i64::MAX);
+        assert_eq!(max + Money::from_cents(1), max);
+        let mut total = Money::from_cents(i64::MAX - 1);
+        total += Money::from_cents(10);
+        assert_eq!(total, max);
+        assert_eq!(
+            Money::from_cents(i64::MIN) + Money::from_cents(-1),
+            Money::from_cents(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn serde_round_trip_keeps_exact_text() {
+        let m: Money = serde_json::from_str("19.9").unwrap();
+        assert_eq!(m, Money::from_cents(1990));
+        assert_eq!(serde_json::to_string(&m).unwrap(), "19.90");
+
+        let big: Money = serde_json::from_str("92233720368547758.07").unwrap();
+        assert_eq!(serde_json::to_string(&big).unwrap(), "92233720368547758.07");
+
+        assert!(serde_json::from_str::<Money>("19.999").is_err());
+        assert!(serde_json::from_str::<Money>("\"19.90\"").is_err());
+    }
+
+    #[test]
+    fn deserializes_inside_internally_tagged_enum() {
+        #[derive(Deserialize, Serialize, Debug, PartialEq)]
+        #[serde(tag = "t")]
+        enum Tagged {
+            Pay { amount: Money },
+        }
+
+        let rec: Tagged = serde_json::from_str(r#"{"t":"Pay","amount":0.1}"#).unwrap();
+        assert_eq!(
+            rec,
+            Tagged::Pay {
+                amount: Money::from_cents(10)
+            }
+        );
+        let line = serde_json::to_string(&rec).unwrap();
+        assert_eq!(line, r#"{"t":"Pay","amount":0.10}"#);
+    }
+}
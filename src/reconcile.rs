@@ -0,0 +1,335 @@
+/// Reconciliação das estatísticas com os registros dos processadores
+/// Compara o nosso resumo com o `GET /admin/payments-summary` de cada
+/// processador na mesma janela e exporta a divergência como gauges
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use chrono::{SecondsFormat, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::Cfg;
+use crate::money::Money;
+use crate::stats::ProcessorSummary;
+use crate::upstream::UpstreamClient;
+use crate::{AppState, PaymentsSummaryQuery};
+
+/// Path do resumo administrativo dos processadores
+const ADMIN_SUMMARY_PATH: &str = "/admin/payments-summary";
+
+/// Resumo devolvido pelo processador
+/// Valores agregados podem vir com ruído de float; arredondamos para centavos
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AdminSummary {
+    total_requests: u64,
+    #[serde(deserialize_with = "Money::deserialize_rounded")]
+    total_amount: Money,
+}
+
+/// Comparação de um processador
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessorDrift {
+    /// O que registramos
+    pub ours: ProcessorSummary,
+    /// O que o processador registrou
+    pub processor: ProcessorSummary,
+    /// processador - nosso (requisições)
+    pub drift_requests: i64,
+    /// processador - nosso (valor)
+    pub drift_amount: Money,
+}
+
+/// Relatório de reconciliação
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconcileReport {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub default: ProcessorDrift,
+    pub fallback: ProcessorDrift,
+    /// `true` se não há divergência em nenhum processador
+    pub consistent: bool,
+}
+
+/// Job de reconciliação contra os processadores
+/// Consultas usam o transporte dos pagamentos (TLS, mTLS, socket Unix, credenciais)
+pub struct Reconciler {
+    /// Cliente do processador default (A)
+    upstream_a: Arc<UpstreamClient>,
+    /// Cliente do processador fallback (B)
+    upstream_b: Arc<UpstreamClient>,
+    /// Token enviado em `X-Rinha-Token`
+    admin_token: String,
+    /// Prazo de cada consulta
+    timeout: Duration,
+    /// Intervalo do job em background (0 = desabilitado)
+    interval: Duration,
+    /// Tamanho da janela verificada pelo job em background
+    window: TimeDelta,
+}
+
+impl Reconciler {
+    /// Cria reconciliador sobre os clientes dos processadores
+    pub fn new(
+        cfg: &Cfg,
+        upstream_a: Arc<UpstreamClient>,
+        upstream_b: Arc<UpstreamClient>,
+    ) -> Self {
+        Self {
+            upstream_a,
+            upstream_b,
+            admin_token: cfg.admin_token.clone(),
+            timeout: Duration::from_millis(cfg.reconcile_timeout_ms),
+            interval: Duration::from_secs(cfg.reconcile_interval_secs),
+            window: TimeDelta::seconds(cfg.reconcile_window_secs as i64),
+        }
+    }
+
+    /// Consulta o resumo administrativo de um processador
+    async fn fetch(
+        &self,
+        up: &UpstreamClient,
+        query: &PaymentsSummaryQuery,
+    ) -> anyhow::Result<ProcessorSummary> {
+        let id = up.id;
+        let mut url = reqwest::Url::parse("http://processor")?.join(ADMIN_SUMMARY_PATH)?;
+        if let Some(from) = &query.from {
+            url.query_pairs_mut().append_pair("from", from);
+        }
+        if let Some(to) = &query.to {
+            url.query_pairs_mut().append_pair("to", to);
+        }
+        let path = match url.query() {
+            Some(q) => format!("{}?{q}", url.path()),
+            None => url.path().to_string(),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Rinha-Token",
+            HeaderValue::from_str(&self.admin_token).context("invalid admin token")?,
+        );
+        let raw = up
+            .get(&path, &headers, self.timeout)
+            .await
+            .with_context(|| format!("processor {id} unreachable"))?;
+        anyhow::ensure!(
+            raw.status.is_success(),
+            "processor {id} failed: {} {}",
+            raw.status,
+            String::from_utf8_lossy(&raw.body).trim()
+        );
+        anyhow::ensure!(
+            !raw.truncated,
+            "processor {id} returned an oversized summary"
+        );
+        let summary: AdminSummary = serde_json::from_slice(&raw.body)
+            .with_context(|| format!("processor {id} returned invalid summary"))?;
+
+        Ok(ProcessorSummary {
+            total_requests: summary.total_requests,
+            total_amount: summary.total_amount,
+        })
+    }
+
+    /// Compara nosso resumo com o dos processadores na janela informada
+    /// Atualiza os gauges de divergência por processador
+    pub async fn reconcile(
+        &self,
+        st: &AppState,
+        query: &PaymentsSummaryQuery,
+    ) -> Result<ReconcileReport, (StatusCode, String)> {
+        // ========== CONSULTAS EM PARALELO ==========
+        let (ours, proc_a, proc_b) = tokio::join!(
+            crate::cluster_summary(st, query),
+            self.fetch(&self.upstream_a, query),
+            self.fetch(&self.upstream_b, query),
+        );
+        let ours = ours?;
+        let to_bad_gateway = |e: anyhow::Error| {
+            metrics::counter!("reconcile_errors").increment(1);
+            (StatusCode::BAD_GATEWAY, format!("{e:#}"))
+        };
+        let proc_a = proc_a.map_err(to_bad_gateway)?;
+        let proc_b = proc_b.map_err(to_bad_gateway)?;
+
+        // ========== DIVERGÊNCIA ==========
+        let default = drift("default", ours.default, proc_a);
+        let fallback = drift("fallback", ours.fallback, proc_b);
+        let consistent = default.drift_requests == 0
+            && default.drift_amount == Money::default()
+            && fallback.drift_requests == 0
+            && fallback.drift_amount == Money::default();
+
+        Ok(ReconcileReport {
+            from: query.from.clone(),
+            to: query.to.clone(),
+            default,
+            fallback,
+            consistent,
+        })
+    }
+
+    /// Loop do job em background
+    /// Verifica periodicamente a janela mais recente
+    pub async fn run(self: Arc<Self>, st: AppState) {
+        if self.interval.is_zero() {
+            return;
+        }
+        info!("reconcile job started");
+
+        loop {
+            tokio::time::sleep(self.interval).await;
+
+            // Ignora o último segundo: pagamentos ainda em voo distorcem a comparação
+            let to = Utc::now() - TimeDelta::seconds(1);
+            let from = to - self.window;
+            let query = PaymentsSummaryQuery {
+                from: Some(from.to_rfc3339_opts(SecondsFormat::Millis, true)),
+                to: Some(to.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            };
+
+            match self.reconcile(&st, &query).await {
+                Ok(report) if !report.consistent => warn!(
+                    "reconcile: drift default={}/{} fallback={}/{}",
+                    report.default.drift_requests,
+                    report.default.drift_amount,
+                    report.fallback.drift_requests,
+                    report.fallback.drift_amount
+                ),
+                Ok(_) => {}
+                Err((_, msg)) => warn!("reconcile: {msg}"),
+            }
+        }
+    }
+}
+
+/// Calcula divergência de um processador e exporta os gauges
+fn drift(
+    name: &'static str,
+    ours: ProcessorSummary,
+    processor: ProcessorSummary,
+) -> ProcessorDrift {
+    let drift_requests = processor.total_requests as i64 - ours.total_requests as i64;
+    let drift_amount =
+        Money::from_cents(processor.total_amount.cents() - ours.total_amount.cents());
+
+    metrics::gauge!("reconcile_drift_requests", "processor" => name).set(drift_requests as f64);
+    metrics::gauge!("reconcile_drift_amount", "processor" => name)
+        .set(drift_amount.cents() as f64 / 100.0);
+
+    ProcessorDrift {
+        ours,
+        processor,
+        drift_requests,
+        drift_amount,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::Router;
+    use axum::extract::{RawQuery, State};
+    use axum::routing::get;
+
+    use super::*;
+    use crate::retry_policy::RetryPolicy;
+    use crate::upstream::UpstreamId;
+
+    /// Última consulta recebida pelo processador falso: (headers, query)
+    type Seen = Arc<Mutex<Option<(HeaderMap, Option<String>)>>>;
+
+    /// Processador falso servindo o resumo administrativo num socket Unix
+    /// (um cliente HTTP comum, sem o transporte configurado, não o alcança)
+    async fn processor(status: StatusCode, body: &'static str) -> (tempfile::TempDir, Seen) {
+        let dir = tempfile::tempdir().unwrap();
+        let seen = Seen::default();
+        let app = Router::new()
+            .route(
+                ADMIN_SUMMARY_PATH,
+                get(
+                    move |State(seen): State<Seen>,
+                          RawQuery(query): RawQuery,
+                          headers: HeaderMap| async move {
+                        *seen.lock().unwrap() = Some((headers, query));
+                        (status, body)
+                    },
+                ),
+            )
+            .with_state(Arc::clone(&seen));
+        let listener = tokio::net::UnixListener::bind(dir.path().join("a.sock")).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (dir, seen)
+    }
+
+    async fn reconciler(dir: &tempfile::TempDir, backend: &str) -> Reconciler {
+        let sock = dir.path().join("a.sock");
+        let cfg = Cfg::from_vars(&[
+            ("UPSTREAM_A_URL", "http://processor-a"),
+            ("UPSTREAM_B_URL", "http://processor-b"),
+            ("UPSTREAM_A_UNIX_SOCKET", sock.to_str().unwrap()),
+            ("UPSTREAM_A_AUTH", "bearer"),
+            ("UPSTREAM_A_AUTH_TOKEN", "s3cret"),
+            ("UPSTREAM_ADMIN_TOKEN", "admin"),
+            ("UPSTREAM_BACKEND", backend),
+        ])
+        .unwrap();
+        let retry = Arc::new(RetryPolicy::new(&cfg));
+        let up = |id| UpstreamClient::new(id, &cfg, Arc::clone(&retry));
+        let up_a = Arc::new(up(UpstreamId::A).await.unwrap());
+        let up_b = Arc::new(up(UpstreamId::B).await.unwrap());
+        Reconciler::new(&cfg, up_a, up_b)
+    }
+
+    fn window() -> PaymentsSummaryQuery {
+        PaymentsSummaryQuery {
+            from: Some("2025-07-15T12:00:00.000Z".into()),
+            to: Some("2025-07-15T12:01:00.000+00:00".into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_goes_through_the_upstream_transport() {
+        for backend in ["reqwest", "hyper"] {
+            let (dir, seen) = processor(
+                StatusCode::OK,
+                r#"{"totalRequests":3,"totalAmount":59.699999999999996}"#,
+            )
+            .await;
+            let r = reconciler(&dir, backend).await;
+
+            let summary = r.fetch(&r.upstream_a, &window()).await.unwrap();
+            assert_eq!(summary.total_requests, 3, "{backend}");
+            assert_eq!(summary.total_amount, Money::from_cents(5970), "{backend}");
+
+            // Credenciais do processador e token administrativo
+            let (headers, query) = seen.lock().unwrap().take().unwrap();
+            assert_eq!(headers["authorization"], "Bearer s3cret", "{backend}");
+            assert_eq!(headers["x-rinha-token"], "admin", "{backend}");
+            assert_eq!(
+                query.as_deref(),
+                Some("from=2025-07-15T12%3A00%3A00.000Z&to=2025-07-15T12%3A01%3A00.000%2B00%3A00"),
+                "{backend}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_reports_processor_errors() {
+        let (dir, _) = processor(StatusCode::UNAUTHORIZED, "bad token").await;
+        let r = reconciler(&dir, "reqwest").await;
+        let err = r.fetch(&r.upstream_a, &window()).await.err().unwrap();
+        assert!(format!("{err:#}").contains("401"), "{err:#}");
+
+        let (dir, _) = processor(StatusCode::OK, r#"{"totalRequests":1}"#).await;
+        let r = reconciler(&dir, "reqwest").await;
+        let err = r.fetch(&r.upstream_a, &window()).await.err().unwrap();
+        assert!(format!("{err:#}").contains("invalid summary"), "{err:#}");
+    }
+}
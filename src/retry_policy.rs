@@ -0,0 +1,159 @@
+/// Política de retry das chamadas aos processadores
+/// Reenvia falhas em que o pagamento não chegou a ser registrado
+/// (conexão não estabelecida, 429/503)
+/// com backoff exponencial e jitter, limitado por um orçamento global para
+/// não multiplicar a carga de um processador que já está sobrecarregado
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+use axum::http::StatusCode;
+
+use crate::config::Cfg;
+use crate::upstream::UpstreamId;
+
+/// Custo de um retry no orçamento (milésimos de token)
+const RETRY_COST: i64 = 1_000;
+
+/// Orçamento global de retries
+/// Cada requisição deposita `ratio` tokens e cada retry consome um, então no
+/// regime permanente retries ficam limitados a `ratio` das requisições
+struct RetryBudget {
+    /// Saldo atual em milésimos de token
+    balance: AtomicI64,
+    /// Depósito por requisição em milésimos de token
+    deposit: i64,
+    /// Saldo máximo acumulado (evita rajadas após longos períodos ociosos)
+    cap: i64,
+}
+
+impl RetryBudget {
+    /// Registra uma requisição nova
+    fn deposit(&self) {
+        let _ = self
+            .balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |b| {
+                Some((b + self.deposit).min(self.cap))
+            });
+    }
+
+    /// Tenta reservar um retry
+    fn withdraw(&self) -> bool {
+        self.balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |b| {
+                (b >= RETRY_COST).then_some(b - RETRY_COST)
+            })
+            .is_ok()
+    }
+}
+
+/// Política compartilhada pelos clientes upstream
+pub struct RetryPolicy {
+    /// Tentativas totais por requisição (1 = sem retry)
+    max_attempts: u32,
+    /// Backoff da primeira retentativa
+    base_backoff: Duration,
+    /// Teto do backoff exponencial
+    max_backoff: Duration,
+    /// Orçamento global (compartilhado entre os processadores)
+    budget: RetryBudget,
+}
+
+impl RetryPolicy {
+    /// Cria política a partir da configuração
+    pub fn new(cfg: &Cfg) -> Self {
+        let deposit = (cfg.upstream_retry_budget_ratio * RETRY_COST as f64) as i64;
+        // Reserva inicial permite alguns retries logo após o boot
+        let reserve = cfg.upstream_retry_min_reserve as i64 * RETRY_COST;
+
+        Self {
+            max_attempts: cfg.upstream_retry_max_attempts.max(1),
+            base_backoff: Duration::from_millis(cfg.upstream_retry_base_backoff_ms),
+            max_backoff: Duration::from_millis(cfg.upstream_retry_max_backoff_ms),
+            budget: RetryBudget {
+                balance: AtomicI64::new(reserve),
+                deposit,
+                cap: reserve.max(RETRY_COST),
+            },
+        }
+    }
+
+    /// Registra uma requisição nova no orçamento
+    pub fn on_request(&self) {
+        self.budget.deposit();
+    }
+
+    /// Decide se a tentativa `attempt` (1 = primeira) que falhou deve ser repetida
+    /// Consome o orçamento apenas quando o retry é de fato permitido
+    pub fn should_retry(&self, upstream: UpstreamId, attempt: u32, retryable: bool) -> bool {
+        if !retryable || attempt >= self.max_attempts {
+            return false;
+        }
+        if !self.budget.withdraw() {
+            metrics::counter!("upstream_retries", "upstream" => upstream.as_str(), "outcome" => "budget_exhausted")
+                .increment(1);
+            return false;
+        }
+        metrics::counter!("upstream_retries", "upstream" => upstream.as_str(), "outcome" => "retried")
+            .increment(1);
+        true
+    }
+
+    /// Backoff antes da tentativa seguinte ("full jitter")
+    /// Sorteado em `[0, min(max, base * 2^(tentativa - 1))]`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << (attempt.saturating_sub(1)).min(16);
+        let ceiling = (self.base_backoff * factor).min(self.max_backoff);
+        let ms = ceiling.as_millis() as u64;
+        Duration::from_millis(rand::random_range(0..=ms))
+    }
+}
+
+/// Status HTTP de rejeição sem processamento, seguros para repetir
+/// 500/502/504 ficam de fora: o processador (ou um proxy na frente dele) pode
+/// ter registrado o pagamento, e o retry com o mesmo correlationId voltaria
+/// como duplicata (4xx), deixando o pagamento cobrado fora do resumo
+pub fn is_retryable_status(sc: StatusCode) -> bool {
+    matches!(
+        sc,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::UpstreamError;
+
+    #[test]
+    fn only_rejections_without_processing_are_retryable() {
+        for sc in [
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            assert!(is_retryable_status(sc), "{sc}");
+        }
+        for sc in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::GATEWAY_TIMEOUT,
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ] {
+            assert!(!is_retryable_status(sc), "{sc}");
+        }
+    }
+
+    #[test]
+    fn failures_after_send_are_not_retryable() {
+        let upstream = UpstreamId::A;
+        let reason = String::from("reset");
+        assert!(
+            UpstreamError::Connect {
+                upstream,
+                reason: reason.clone()
+            }
+            .is_retryable()
+        );
+        assert!(!UpstreamError::Interrupted { upstream, reason }.is_retryable());
+        assert!(!UpstreamError::Timeout { upstream }.is_retryable());
+    }
+}
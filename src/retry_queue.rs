@@ -0,0 +1,330 @@
+/// Fila de retry para pagamentos que falharam nos dois processadores
+/// Mantém pagamentos válidos em memória (fila limitada) e reprocessa com
+/// backoff exponencial assim que algum circuit breaker volta a aceitar tráfego
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::AppState;
+use crate::config::Cfg;
+use crate::dispatch::Payment;
+use crate::upstream::{UpstreamError, UpstreamId};
+use crate::wal::WalRecord;
+
+/// Quantidade máxima de pagamentos reprocessados por varredura
+const BATCH_SIZE: usize = 64;
+
+/// Pagamento pendente de reprocessamento
+pub struct RetryItem {
+    /// Pagamento original (mesmo correlationId e requestedAt em todas as tentativas)
+    pub payment: Payment,
+    /// Tentativas já realizadas pela fila
+    attempts: u32,
+    /// Momento a partir do qual o item pode ser reprocessado
+    next_at: Instant,
+    /// Processador que pode ter registrado o pagamento numa tentativa anterior
+    /// (timeout, conexão interrompida); reenvios vão só para ele
+    maybe_processed_by: Option<UpstreamId>,
+}
+
+impl RetryItem {
+    /// Cria item pronto para a primeira retentativa
+    pub fn new(payment: Payment) -> Self {
+        Self {
+            payment,
+            attempts: 0,
+            next_at: Instant::now(),
+            maybe_processed_by: None,
+        }
+    }
+
+    /// Cria item para um pagamento cuja tentativa terminou com `err`
+    pub fn after_failure(payment: Payment, err: &UpstreamError) -> Self {
+        let mut item = Self::new(payment);
+        item.note_failure(err);
+        item
+    }
+
+    /// Fixa o processador se a falha deixou o desfecho incerto
+    /// O primeiro processador fixado permanece: é lá que a duplicata estaria
+    fn note_failure(&mut self, err: &UpstreamError) {
+        if err.maybe_processed() {
+            self.maybe_processed_by.get_or_insert(err.upstream());
+        }
+    }
+}
+
+/// Item na heap da fila: o topo é o de `next_at` mais cedo
+struct Scheduled(RetryItem);
+
+impl Scheduled {
+    fn key(&self) -> Reverse<Instant> {
+        Reverse(self.0.next_at)
+    }
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Fila limitada de pagamentos aguardando nova tentativa
+pub struct RetryQueue {
+    /// Capacidade máxima da fila
+    capacity: usize,
+    /// Tentativas antes de descartar o pagamento
+    max_attempts: u32,
+    /// Backoff da primeira retentativa
+    base_backoff: Duration,
+    /// Teto do backoff exponencial
+    max_backoff: Duration,
+    /// Intervalo entre varreduras do worker
+    tick: Duration,
+    /// Itens pendentes, ordenados pelo próximo reprocessamento
+    items: Mutex<BinaryHeap<Scheduled>>,
+}
+
+impl RetryQueue {
+    /// Cria fila vazia a partir da configuração
+    pub fn new(cfg: &Cfg) -> Self {
+        Self {
+            capacity: cfg.retry_queue_capacity,
+            max_attempts: cfg.retry_max_attempts,
+            base_backoff: Duration::from_millis(cfg.retry_base_backoff_ms),
+            max_backoff: Duration::from_millis(cfg.retry_max_backoff_ms),
+            tick: Duration::from_millis(cfg.retry_tick_ms),
+            items: Mutex::new(BinaryHeap::new()),
+        }
+    }
+
+    /// Enfileira pagamento para nova tentativa
+    /// # Returns
+    /// * `true` se o pagamento foi aceito na fila
+    /// * `false` se a fila está cheia
+    pub fn push(&self, mut item: RetryItem) -> bool {
+        let mut items = self.items.lock().unwrap();
+        if items.len() >= self.capacity {
+            metrics::counter!("payments_retry_rejected").increment(1);
+            return false;
+        }
+
+        item.next_at = Instant::now() + self.base_backoff;
+        items.push(Scheduled(item));
+        metrics::counter!("payments_retry_enqueued").increment(1);
+        metrics::gauge!("payments_retry_queue_len").set(items.len() as f64);
+        true
+    }
+
+    /// Retira da fila os itens cujo backoff já expirou (mais antigos primeiro)
+    /// Só percorre os vencidos: O(k log n) para k itens retirados
+    fn take_due(&self, now: Instant) -> Vec<RetryItem> {
+        let mut items = self.items.lock().unwrap();
+        let mut due = Vec::new();
+        while due.len() < BATCH_SIZE && items.peek().is_some_and(|top| top.0.next_at <= now) {
+            due.extend(items.pop().map(|top| top.0));
+        }
+        metrics::gauge!("payments_retry_queue_len").set(items.len() as f64);
+        due
+    }
+
+    /// Devolve item à fila com backoff exponencial
+    /// Descarta o pagamento se o número máximo de tentativas foi atingido
+    async fn reschedule(&self, st: &AppState, mut item: RetryItem) {
+        item.attempts += 1;
+        if item.attempts >= self.max_attempts {
+            warn!(
+                "retry: dropping payment {} after {} attempts",
+                item.payment.correlation_id, item.attempts
+            );
+            st.wal_append(&WalRecord::Failed {
+                id: item.payment.correlation_id,
+            })
+            .await;
+            metrics::counter!("payments_retry_dropped", "reason" => "max_attempts").increment(1);
+            return;
+        }
+
+        // base * 2^(tentativas - 1), limitado ao backoff máximo
+        let factor = 1u32 << (item.attempts - 1).min(16);
+        item.next_at = Instant::now() + (self.base_backoff * factor).min(self.max_backoff);
+
+        // Itens já aceitos não disputam capacidade com novos pagamentos
+        let mut items = self.items.lock().unwrap();
+        items.push(Scheduled(item));
+        metrics::gauge!("payments_retry_queue_len").set(items.len() as f64);
+    }
+
+    /// Loop do worker de retry
+    /// Só reprocessa enquanto pelo menos um circuit breaker estiver fechado
+    pub async fn run(self: Arc<Self>, st: AppState) {
+        info!("retry worker started");
+        loop {
+            tokio::time::sleep(self.tick).await;
+
+            // ========== AGUARDA RECUPERAÇÃO ==========
+            // Com os dois circuitos abertos não há para onde enviar
+            if st.dispatcher.all_open() {
+                continue;
+            }
+
+            let due = self.take_due(Instant::now());
+            if due.is_empty() {
+                continue;
+            }
+
+            futures::future::join_all(due.into_iter().map(|item| self.attempt(&st, item))).await;
+        }
+    }
+
+    /// Executa uma nova tentativa de um pagamento pendente
+    async fn attempt(&self, st: &AppState, mut item: RetryItem) {
+        // ========== REENVIO ==========
+        // Sem hedging: uma tentativa por varredura, evitando processador com circuito aberto
+        // (ou só no processador fixado, quando o desfecho anterior foi incerto)
+        match st
+            .dispatcher
+            .redeliver(&item.payment, item.maybe_processed_by)
+            .await
+        {
+            Ok(_) => {
+                metrics::counter!("payments_retry_ok").increment(1);
+            }
+            Err(e) => {
+                if e.is_upstream_fault() {
+                    // Falha do processador - tenta novamente mais tarde
+                    metrics::counter!("payments_retry_err", "code" => e.status().as_u16().to_string(), "kind" => e.kind())
+                        .increment(1);
+                    item.note_failure(&e);
+                    self.reschedule(st, item).await;
+                } else {
+                    // Processador rejeitou o pagamento - não adianta insistir
+                    warn!(
+                        "retry: payment {} rejected: {e}",
+                        item.payment.correlation_id
+                    );
+                    st.wal_append(&WalRecord::Failed {
+                        id: item.payment.correlation_id,
+                    })
+                    .await;
+                    metrics::counter!("payments_retry_dropped", "reason" => "rejected")
+                        .increment(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Money;
+
+    fn queue(capacity: usize) -> RetryQueue {
+        let capacity = capacity.to_string();
+        let cfg = Cfg::from_vars(&[
+            ("UPSTREAM_A_URL", "http://a"),
+            ("UPSTREAM_B_URL", "http://b"),
+            ("RETRY_QUEUE_CAPACITY", &capacity),
+            ("RETRY_BASE_BACKOFF_MS", "100"),
+        ])
+        .unwrap();
+        RetryQueue::new(&cfg)
+    }
+
+    /// Item já na fila com o prazo dado
+    fn schedule(q: &RetryQueue, cents: i64, next_at: Instant) {
+        let mut item = RetryItem::new(Payment::new(Money::from_cents(cents)));
+        item.next_at = next_at;
+        q.items.lock().unwrap().push(Scheduled(item));
+    }
+
+    fn cents(items: &[RetryItem]) -> Vec<i64> {
+        items.iter().map(|i| i.payment.amount.cents()).collect()
+    }
+
+    #[test]
+    fn take_due_returns_only_expired_items_earliest_first() {
+        let q = queue(16);
+        let now = Instant::now();
+        schedule(&q, 3, now + Duration::from_millis(30));
+        schedule(&q, 9, now + Duration::from_secs(60));
+        schedule(&q, 1, now + Duration::from_millis(10));
+        schedule(&q, 2, now + Duration::from_millis(20));
+
+        assert!(q.take_due(now).is_empty());
+        assert_eq!(cents(&q.take_due(now + Duration::from_millis(25))), [1, 2]);
+        assert_eq!(cents(&q.take_due(now + Duration::from_secs(1))), [3]);
+        assert_eq!(q.items.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn take_due_is_limited_to_one_batch() {
+        let q = queue(1_000);
+        let now = Instant::now();
+        for i in 0..(BATCH_SIZE as i64 + 10) {
+            schedule(&q, i, now);
+        }
+
+        assert_eq!(q.take_due(now).len(), BATCH_SIZE);
+        assert_eq!(q.take_due(now).len(), 10);
+    }
+
+    #[test]
+    fn push_waits_base_backoff_and_respects_capacity() {
+        let q = queue(2);
+        let now = Instant::now();
+        for cents in 1..=2 {
+            assert!(q.push(RetryItem::new(Payment::new(Money::from_cents(cents)))));
+        }
+        assert!(!q.push(RetryItem::new(Payment::new(Money::from_cents(3)))));
+
+        assert!(q.take_due(now).is_empty());
+        let due = q.take_due(now + Duration::from_millis(200));
+        assert_eq!(due.len(), 2);
+    }
+
+    #[test]
+    fn after_failure_pins_only_uncertain_outcomes() {
+        let p = || Payment::new(Money::from_cents(1));
+        let connect = UpstreamError::Connect {
+            upstream: UpstreamId::A,
+            reason: "refused".into(),
+        };
+        assert_eq!(
+            RetryItem::after_failure(p(), &connect).maybe_processed_by,
+            None
+        );
+
+        let mut item = RetryItem::after_failure(
+            p(),
+            &UpstreamError::Timeout {
+                upstream: UpstreamId::B,
+            },
+        );
+        assert_eq!(item.maybe_processed_by, Some(UpstreamId::B));
+
+        // O primeiro processador fixado permanece
+        item.note_failure(&UpstreamError::Timeout {
+            upstream: UpstreamId::A,
+        });
+        assert_eq!(item.maybe_processed_by, Some(UpstreamId::B));
+    }
+}
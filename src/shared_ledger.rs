@@ -0,0 +1,362 @@
+/// Ledger de pagamentos compartilhado entre instâncias no mesmo host
+/// Arquivo mapeado em memória (mmap) num volume compartilhado: todas as
+/// instâncias fazem append no mesmo ledger usando apenas operações atômicas,
+/// então qualquer uma responde `/payments-summary` e `/purge-payments`
+///
+/// Layout do arquivo (palavras de 64 bits):
+/// * Header: magic, capacidade, sequência do próximo registro, sequência do último purge,
+///   maior `requestedAt` sobrescrito desde o purge
+/// * Registros: `requestedAt` (ms), valor (centavos), tag (sequência + processador)
+///
+/// Os registros formam um buffer circular: a sequência `n` ocupa o slot
+/// `n % capacidade`, e o ledger guarda os `capacidade` pagamentos mais recentes.
+/// A tag identifica qual sequência está no slot, então leitores descartam
+/// registros em escrita ou já sobrescritos (protocolo de seqlock)
+///
+/// Registros sobrescritos somem do resumo: uma janela que alcança o maior
+/// `requestedAt` já sobrescrito é recusada (`Evicted`) em vez de subcontar
+use std::fs::OpenOptions;
+use std::sync::atomic::{AtomicU64, Ordering, fence};
+
+use anyhow::Context;
+use memmap2::MmapMut;
+use tracing::warn;
+
+use crate::money::Money;
+use crate::stats::{PaymentSummary, ProcessorSummary};
+use crate::upstream::UpstreamId;
+
+/// Identifica arquivos inicializados pelo p99 (layout circular)
+const MAGIC: u64 = 0x7039_395f_4c44_4732; // "p99_LDG2"
+/// Palavras do header
+const HEADER_WORDS: usize = 8;
+/// Palavras por registro
+const RECORD_WORDS: usize = 3;
+
+/// Índices das palavras do header
+const H_MAGIC: usize = 0;
+const H_CAPACITY: usize = 1;
+const H_LEN: usize = 2;
+const H_PURGED: usize = 3;
+const H_EVICTED_MS: usize = 4;
+
+/// Códigos de processador gravados nos 2 bits baixos da tag
+const PROC_DEFAULT: u64 = 1;
+const PROC_FALLBACK: u64 = 2;
+const PROC_MASK: u64 = 0b11;
+
+/// Janela do resumo alcança registros já sobrescritos
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "payments up to requestedAt {evicted_until_ms} ms were overwritten (LEDGER_CAPACITY reached)"
+)]
+pub struct Evicted {
+    /// Maior `requestedAt` (ms) entre os registros sobrescritos
+    pub evicted_until_ms: i64,
+}
+
+/// `requestedAt` (ms) codificado para comparação sem sinal (`fetch_max`)
+/// Zero (palavra nova) equivale a `i64::MIN`: nada sobrescrito
+fn ordered_ms(ms: i64) -> u64 {
+    (ms as u64) ^ (1 << 63)
+}
+
+/// Inverso de `ordered_ms`
+fn from_ordered_ms(word: u64) -> i64 {
+    (word ^ (1 << 63)) as i64
+}
+
+/// Ledger circular em memória compartilhada
+pub struct SharedLedger {
+    /// Mapeamento do arquivo (mantido vivo enquanto o ledger existir)
+    map: MmapMut,
+    /// Número de registros mantidos
+    capacity: u64,
+    /// Palavras de 64 bits disponíveis no mapeamento
+    words: usize,
+}
+
+impl SharedLedger {
+    /// Abre (ou cria) o ledger compartilhado
+    /// Seguro para várias instâncias abrirem o mesmo arquivo ao mesmo tempo
+    /// # Arguments
+    /// * `path` - Arquivo no volume compartilhado
+    /// * `capacity` - Número de pagamentos mantidos (igual em todas as instâncias)
+    pub fn open(path: &str, capacity: u64) -> anyhow::Result<Self> {
+        anyhow::ensure!(capacity > 0, "ledger capacity must be positive");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("open ledger {path}"))?;
+
+        // ========== TAMANHO DO ARQUIVO ==========
+        // Só cresce: quem chegar depois não trunca o ledger existente
+        let size = (HEADER_WORDS as u64 + capacity * RECORD_WORDS as u64) * 8;
+        if file.metadata()?.len() < size {
+            file.set_len(size)?;
+        }
+
+        // SAFETY: o arquivo é exclusivo do ledger; acessos concorrentes (inclusive
+        // de outros processos) passam sempre pelas palavras atômicas abaixo
+        let map = unsafe { MmapMut::map_mut(&file) }.context("mmap ledger")?;
+        let words = map.len() / 8;
+        let ledger = Self {
+            map,
+            capacity,
+            words,
+        };
+
+        // ========== INICIALIZAÇÃO DO HEADER ==========
+        // Apenas a primeira instância grava a capacidade
+        match ledger
+            .word(H_MAGIC)
+            .compare_exchange(0, MAGIC, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => ledger.word(H_CAPACITY).store(capacity, Ordering::Release),
+            Err(MAGIC) => {}
+            Err(_) => anyhow::bail!("{path} is not a p99 ledger (or uses an older layout)"),
+        }
+
+        // Todas as instâncias precisam usar a mesma capacidade: com valores
+        // diferentes o mapeamento sequência -> slot não bateria entre elas
+        let existing = loop {
+            let c = ledger.word(H_CAPACITY).load(Ordering::Acquire);
+            if c != 0 {
+                break c;
+            }
+            std::hint::spin_loop();
+        };
+        anyhow::ensure!(
+            existing == capacity,
+            "ledger {path} was created with capacity {existing}, LEDGER_CAPACITY is {capacity}"
+        );
+        Ok(ledger)
+    }
+
+    /// Palavra atômica na posição indicada
+    fn word(&self, idx: usize) -> &AtomicU64 {
+        assert!(idx < self.words, "ledger word {idx} out of bounds");
+        let ptr = self.map.as_ptr() as *const AtomicU64;
+        // SAFETY: índice verificado acima, mmap é alinhado à página e
+        // AtomicU64 tem o layout de u64
+        unsafe { &*ptr.add(idx) }
+    }
+
+    /// Palavra `field` do slot da sequência `seq`
+    fn record(&self, seq: u64, field: usize) -> &AtomicU64 {
+        let slot = (seq % self.capacity) as usize;
+        self.word(HEADER_WORDS + slot * RECORD_WORDS + field)
+    }
+
+    /// Acrescenta pagamento confirmado ao ledger
+    /// Com o ledger cheio sobrescreve o registro mais antigo e marca seu
+    /// `requestedAt` como sobrescrito
+    pub fn append(&self, upstream: UpstreamId, amount: Money, at_ms: i64) {
+        let seq = self.word(H_LEN).fetch_add(1, Ordering::AcqRel);
+        let tag = self.record(seq, 2);
+        if seq >= self.capacity
+            && seq - self.capacity >= self.word(H_PURGED).load(Ordering::Acquire)
+        {
+            // Registro sobrescrito ainda contava no resumo
+            let old = seq - self.capacity;
+            let evicted_ms = if tag.load(Ordering::Acquire) >> 2 == old + 1 {
+                self.record(seq, 0).load(Ordering::Relaxed) as i64
+            } else {
+                // Antigo ainda em escrita: sem o instante, recusa qualquer janela
+                i64::MAX
+            };
+            let prev = self
+                .word(H_EVICTED_MS)
+                .fetch_max(ordered_ms(evicted_ms), Ordering::AcqRel);
+            if prev == 0 {
+                warn!(
+                    "ledger: capacity {} reached, overwriting oldest payments",
+                    self.capacity
+                );
+            }
+            metrics::counter!("ledger_evicted").increment(1);
+        }
+
+        // Tag zerada invalida o slot antes de trocar os dados
+        tag.store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.record(seq, 0).store(at_ms as u64, Ordering::Relaxed);
+        self.record(seq, 1)
+            .store(amount.cents() as u64, Ordering::Relaxed);
+        // Tag por último: publica o registro para os leitores
+        let code = match upstream {
+            UpstreamId::A => PROC_DEFAULT,
+            UpstreamId::B => PROC_FALLBACK,
+        };
+        tag.store(((seq + 1) << 2) | code, Ordering::Release);
+    }
+
+    /// Resumo dos registros com `requestedAt` dentro de `[from_ms, to_ms]`
+    /// Percorre até `capacity` registros: chamar fora de locks do caminho quente
+    /// # Returns
+    /// * `Err(Evicted)` - A janela inclui pagamentos já sobrescritos
+    pub fn summary(&self, from_ms: i64, to_ms: i64) -> Result<PaymentSummary, Evicted> {
+        let evicted = self.word(H_EVICTED_MS).load(Ordering::Acquire);
+        if evicted != 0 && from_ms <= from_ordered_ms(evicted) {
+            metrics::counter!("ledger_summary_evicted").increment(1);
+            return Err(Evicted {
+                evicted_until_ms: from_ordered_ms(evicted),
+            });
+        }
+
+        let mut out = PaymentSummary::default();
+        let len = self.word(H_LEN).load(Ordering::Acquire);
+        let start = self
+            .word(H_PURGED)
+            .load(Ordering::Acquire)
+            .max(len.saturating_sub(self.capacity));
+
+        for seq in start..len {
+            let tag = self.record(seq, 2);
+            let before = tag.load(Ordering::Acquire);
+            // Slot em escrita ou já reaproveitado por uma sequência mais nova
+            if before >> 2 != seq + 1 {
+                continue;
+            }
+            let at_ms = self.record(seq, 0).load(Ordering::Relaxed) as i64;
+            let cents = self.record(seq, 1).load(Ordering::Relaxed) as i64;
+            fence(Ordering::Acquire);
+            if tag.load(Ordering::Relaxed) != before {
+                continue; // Sobrescrito durante a leitura
+            }
+
+            let target: &mut ProcessorSummary = match before & PROC_MASK {
+                PROC_DEFAULT => &mut out.default,
+                PROC_FALLBACK => &mut out.fallback,
+                _ => continue,
+            };
+            if at_ms < from_ms || at_ms > to_ms {
+                continue;
+            }
+            target.total_requests += 1;
+            target.total_amount += Money::from_cents(cents);
+        }
+        Ok(out)
+    }
+
+    /// Descarta todos os registros atuais para todas as instâncias
+    pub fn purge(&self) {
+        let len = self.word(H_LEN).load(Ordering::Acquire);
+        self.word(H_PURGED).fetch_max(len, Ordering::AcqRel);
+        self.word(H_EVICTED_MS).store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(dir: &tempfile::TempDir, capacity: u64) -> anyhow::Result<SharedLedger> {
+        SharedLedger::open(dir.path().join("ledger").to_str().unwrap(), capacity)
+    }
+
+    fn totals(ledger: &SharedLedger) -> (u64, i64, u64, i64) {
+        let s = ledger.summary(i64::MIN, i64::MAX).unwrap();
+        (
+            s.default.total_requests,
+            s.default.total_amount.cents(),
+            s.fallback.total_requests,
+            s.fallback.total_amount.cents(),
+        )
+    }
+
+    #[test]
+    fn append_summary_and_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = open(&dir, 16).unwrap();
+        ledger.append(UpstreamId::A, Money::from_cents(100), 1_000);
+        ledger.append(UpstreamId::B, Money::from_cents(250), 2_000);
+        ledger.append(UpstreamId::A, Money::from_cents(50), 3_000);
+
+        assert_eq!(totals(&ledger), (2, 150, 1, 250));
+        let s = ledger.summary(1_500, 3_000).unwrap();
+        assert_eq!(s.default.total_requests, 1);
+        assert_eq!(s.fallback.total_requests, 1);
+    }
+
+    #[test]
+    fn windows_reaching_overwritten_records_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = open(&dir, 4).unwrap();
+        for i in 0..10 {
+            ledger.append(UpstreamId::A, Money::from_cents(i), i * 1_000);
+        }
+
+        // Sequências 0..6 sobrescritas: janelas que as alcançam não subcontam
+        let err = ledger.summary(i64::MIN, i64::MAX).err().unwrap();
+        assert_eq!(err.evicted_until_ms, 5_000);
+        assert!(ledger.summary(5_000, 9_000).is_err());
+
+        // Depois do último sobrescrito o resumo continua exato
+        let s = ledger.summary(5_001, 9_000).unwrap();
+        assert_eq!(s.default.total_requests, 4);
+        assert_eq!(s.default.total_amount.cents(), 6 + 7 + 8 + 9);
+    }
+
+    #[test]
+    fn purge_clears_the_eviction_mark() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = open(&dir, 2).unwrap();
+        for i in 0..3 {
+            ledger.append(UpstreamId::A, Money::from_cents(1), i);
+        }
+        assert!(ledger.summary(i64::MIN, i64::MAX).is_err());
+
+        // Reaproveitar slots de registros anteriores ao purge não marca nada
+        ledger.purge();
+        ledger.append(UpstreamId::B, Money::from_cents(5), 0);
+        ledger.append(UpstreamId::B, Money::from_cents(5), 0);
+        assert_eq!(totals(&ledger), (0, 0, 2, 10));
+    }
+
+    #[test]
+    fn purge_then_keep_appending_past_capacity() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = open(&dir, 4).unwrap();
+        for _ in 0..3 {
+            ledger.append(UpstreamId::B, Money::from_cents(10), 0);
+        }
+        ledger.purge();
+        assert_eq!(totals(&ledger), (0, 0, 0, 0));
+
+        for _ in 0..3 {
+            ledger.append(UpstreamId::A, Money::from_cents(10), 0);
+        }
+        assert_eq!(totals(&ledger), (3, 30, 0, 0));
+    }
+
+    #[test]
+    fn instances_share_records_and_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = open(&dir, 8).unwrap();
+        let b = open(&dir, 8).unwrap();
+        a.append(UpstreamId::A, Money::from_cents(100), 0);
+        b.append(UpstreamId::B, Money::from_cents(200), 0);
+        assert_eq!(totals(&a), (1, 100, 1, 200));
+        assert_eq!(totals(&b), totals(&a));
+
+        b.purge();
+        assert_eq!(totals(&a), (0, 0, 0, 0));
+        drop((a, b));
+
+        let reopened = open(&dir, 8).unwrap();
+        reopened.append(UpstreamId::A, Money::from_cents(1), 0);
+        assert_eq!(totals(&reopened), (1, 1, 0, 0));
+    }
+
+    #[test]
+    fn rejects_capacity_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let _first = open(&dir, 8).unwrap();
+        let err = open(&dir, 16).err().unwrap();
+        assert!(err.to_string().contains("capacity 8"), "{err}");
+    }
+}
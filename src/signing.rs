@@ -0,0 +1,134 @@
+/// Assinatura das requisições de saída para os processadores
+/// PSPs reais exigem requisições assinadas (timestamp + digest do corpo);
+/// a assinatura é calculada a cada tentativa sobre os bytes exatos enviados
+use std::fmt::Write;
+use std::sync::Arc;
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use http::{HeaderMap, HeaderName, HeaderValue, Method};
+use ring::{digest, hmac};
+
+use crate::config::UpstreamSigning;
+
+/// Header com o digest SHA-256 do corpo (`SHA-256=<base64>`)
+const DIGEST_HEADER: HeaderName = HeaderName::from_static("digest");
+/// Header com o identificador da chave, quando configurado
+const KEY_ID_HEADER: HeaderName = HeaderName::from_static("x-key-id");
+
+/// Ponto de extensão para esquemas de assinatura
+pub trait RequestSigner: Send + Sync {
+    /// Acrescenta os headers de assinatura da requisição
+    /// # Arguments
+    /// * `path` - Path (com query) da requisição
+    /// * `body` - Corpo exato que será enviado
+    fn sign(&self, method: &Method, path: &str, body: &[u8], headers: &mut HeaderMap);
+}
+
+/// Cria o assinador configurado para o processador
+pub fn from_cfg(cfg: &UpstreamSigning) -> anyhow::Result<Option<Arc<dyn RequestSigner>>> {
+    Ok(match cfg {
+        UpstreamSigning::None => None,
+        UpstreamSigning::HmacSha256 {
+            key,
+            key_id,
+            signature_header,
+            timestamp_header,
+        } => Some(Arc::new(HmacSha256Signer {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+            key_id: key_id.as_deref().map(HeaderValue::from_str).transpose()?,
+            signature_header: HeaderName::from_bytes(signature_header.as_bytes())?,
+            timestamp_header: HeaderName::from_bytes(timestamp_header.as_bytes())?,
+        })),
+    })
+}
+
+/// HMAC-SHA256 sobre `"{timestamp}\n{MÉTODO}\n{path}\n{digest}"`
+/// Timestamp em segundos Unix; assinatura em hex minúsculo
+struct HmacSha256Signer {
+    key: hmac::Key,
+    key_id: Option<HeaderValue>,
+    signature_header: HeaderName,
+    timestamp_header: HeaderName,
+}
+
+impl RequestSigner for HmacSha256Signer {
+    fn sign(&self, method: &Method, path: &str, body: &[u8], headers: &mut HeaderMap) {
+        self.sign_at(chrono::Utc::now().timestamp(), method, path, body, headers);
+    }
+}
+
+impl HmacSha256Signer {
+    /// Assina com o timestamp dado (segundos Unix)
+    fn sign_at(
+        &self,
+        timestamp: i64,
+        method: &Method,
+        path: &str,
+        body: &[u8],
+        headers: &mut HeaderMap,
+    ) {
+        let timestamp = timestamp.to_string();
+        let digest = format!(
+            "SHA-256={}",
+            BASE64_STANDARD.encode(digest::digest(&digest::SHA256, body))
+        );
+
+        let message = format!("{timestamp}\n{method}\n{path}\n{digest}");
+        let tag = hmac::sign(&self.key, message.as_bytes());
+        let mut signature = String::with_capacity(64);
+        for byte in tag.as_ref() {
+            let _ = write!(signature, "{byte:02x}");
+        }
+
+        // Valores montados só com ASCII seguro (dígitos, hex e base64)
+        headers.insert(
+            &self.timestamp_header,
+            HeaderValue::from_str(&timestamp).unwrap(),
+        );
+        headers.insert(DIGEST_HEADER, HeaderValue::from_str(&digest).unwrap());
+        headers.insert(
+            &self.signature_header,
+            HeaderValue::from_str(&signature).unwrap(),
+        );
+        if let Some(key_id) = &self.key_id {
+            headers.insert(KEY_ID_HEADER, key_id.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vetor calculado à parte com `openssl dgst -sha256 [-hmac]` (ver README)
+    #[test]
+    fn hmac_sha256_matches_known_vector() {
+        let signer = HmacSha256Signer {
+            key: hmac::Key::new(hmac::HMAC_SHA256, b"psp-shared-secret"),
+            key_id: Some(HeaderValue::from_static("p99-1")),
+            signature_header: HeaderName::from_static("x-signature"),
+            timestamp_header: HeaderName::from_static("x-timestamp"),
+        };
+        let body = br#"{"correlationId":"4a7901b8-7d26-4d9d-aa19-4dc1c7cf60b3","amount":19.90,"requestedAt":"2025-07-15T12:34:56.000Z"}"#;
+
+        let mut headers = HeaderMap::new();
+        signer.sign_at(
+            1_700_000_000,
+            &Method::POST,
+            "/payments?source=p99",
+            body,
+            &mut headers,
+        );
+
+        assert_eq!(headers["x-timestamp"], "1700000000");
+        assert_eq!(
+            headers["digest"],
+            "SHA-256=35avC52+ZAVs0YcZr02xLmN1pbzQTPoC2djofEguxQw="
+        );
+        assert_eq!(
+            headers["x-signature"],
+            "0c290848ddc59e04a18ef9398b2ec808512c18215621b4cb0d0c11e7102a287a"
+        );
+        assert_eq!(headers["x-key-id"], "p99-1");
+    }
+}
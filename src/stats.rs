@@ -0,0 +1,296 @@
+/// Estatísticas de pagamentos indexadas por tempo
+/// Cada pagamento confirmado é agregado pelo seu `requestedAt` (milissegundos),
+/// permitindo resumos exatos para qualquer janela `[from, to]`
+/// Opcionalmente delega para um ledger compartilhado entre instâncias
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+
+use crate::money::Money;
+use crate::shared_ledger::{Evicted, SharedLedger};
+use crate::upstream::UpstreamId;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Estatísticas globais de processamento de pagamentos
+/// Separadas por processador (default/fallback)
+#[derive(Default)]
+pub struct PaymentStats {
+    default: ProcessorStats,           // Estatísticas do Payment Processor A
+    fallback: ProcessorStats,          // Estatísticas do Payment Processor B
+    shared: Option<Arc<SharedLedger>>, // Ledger compartilhado (substitui os mapas locais)
+}
+
+/// Estatísticas por processador individual
+/// Chave: `requestedAt` em milissegundos desde a epoch
+#[derive(Default)]
+struct ProcessorStats {
+    by_ms: BTreeMap<i64, Bucket>,
+}
+
+/// Agregado dos pagamentos de um mesmo milissegundo
+#[derive(Default)]
+struct Bucket {
+    total_requests: u64, // Total de requests processados
+    total_amount: Money, // Valor total processado (centavos)
+}
+
+/// Resumo de pagamentos no formato da Rinha
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentSummary {
+    pub default: ProcessorSummary,
+    pub fallback: ProcessorSummary,
+}
+
+/// Resumo de um processador
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessorSummary {
+    pub total_requests: u64,
+    pub total_amount: Money,
+}
+
+impl PaymentStats {
+    /// Estatísticas apoiadas no ledger compartilhado entre instâncias
+    pub fn shared(ledger: SharedLedger) -> Self {
+        Self {
+            shared: Some(Arc::new(ledger)),
+            ..Self::default()
+        }
+    }
+
+    /// Ledger compartilhado, para resumos fora do lock das estatísticas
+    pub fn ledger(&self) -> Option<Arc<SharedLedger>> {
+        self.shared.clone()
+    }
+
+    /// Indica se as estatísticas já refletem todas as instâncias
+    pub fn is_shared(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    /// Contabiliza pagamento confirmado por um processador
+    /// # Arguments
+    /// * `upstream` - Processador que confirmou (A = default, B = fallback)
+    /// * `amount` - Valor do pagamento
+    /// * `requested_at` - `requestedAt` enviado ao processador (RFC 3339)
+    pub fn add(&mut self, upstream: UpstreamId, amount: Money, requested_at: &str) {
+        // requestedAt é sempre gerado por nós; em caso de valor inválido
+        // (ex: WAL antigo) usa o instante atual para não perder o pagamento
+        let at_ms = parse_timestamp(requested_at)
+            .unwrap_or_else(|_| Utc::now())
+            .timestamp_millis();
+
+        if let Some(ledger) = &self.shared {
+            ledger.append(upstream, amount, at_ms);
+            return;
+        }
+
+        let p = match upstream {
+            UpstreamId::A => &mut self.default,
+            UpstreamId::B => &mut self.fallback,
+        };
+        let bucket = p.by_ms.entry(at_ms).or_default();
+        bucket.total_requests += 1;
+        bucket.total_amount += amount;
+    }
+
+    /// Resumo dos pagamentos com `requestedAt` dentro de `[from, to]`
+    /// Limites ausentes significam janela aberta
+    /// # Returns
+    /// * `Err(Evicted)` - Janela alcança registros sobrescritos do ledger compartilhado
+    pub fn summary(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<PaymentSummary, Evicted> {
+        let (from, to) = window_ms(from, to);
+        if let Some(ledger) = &self.shared {
+            return ledger.summary(from, to);
+        }
+        Ok(PaymentSummary {
+            default: self.default.summary(from, to),
+            fallback: self.fallback.summary(from, to),
+        })
+    }
+
+    /// Zera todas as estatísticas
+    pub fn clear(&mut self) {
+        if let Some(ledger) = &self.shared {
+            ledger.purge(); // Vale para todas as instâncias
+        }
+        self.default.by_ms.clear();
+        self.fallback.by_ms.clear();
+    }
+}
+
+/// Janela `[from, to]` em milissegundos; limites ausentes viram janela aberta
+pub fn window_ms(from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> (i64, i64) {
+    (
+        from.map_or(i64::MIN, |t| t.timestamp_millis()),
+        to.map_or(i64::MAX, |t| t.timestamp_millis()),
+    )
+}
+
+impl PaymentSummary {
+    /// Soma outro resumo a este (agregação entre instâncias)
+    pub fn merge(&mut self, other: &PaymentSummary) {
+        self.default.total_requests += other.default.total_requests;
+        self.default.total_amount += other.default.total_amount;
+        self.fallback.total_requests += other.fallback.total_requests;
+        self.fallback.total_amount += other.fallback.total_amount;
+    }
+}
+
+impl ProcessorStats {
+    /// Soma os buckets dentro do intervalo (inclusivo)
+    fn summary(&self, from_ms: i64, to_ms: i64) -> ProcessorSummary {
+        let mut out = ProcessorSummary::default();
+        if from_ms > to_ms {
+            return out;
+        }
+        for bucket in self.by_ms.range(from_ms..=to_ms).map(|(_, b)| b) {
+            out.total_requests += bucket.total_requests;
+            out.total_amount += bucket.total_amount;
+        }
+        out
+    }
+}
+
+/// Timestamp atual no formato enviado aos processadores
+/// Precisão de milissegundos, igual à usada no índice e na auditoria
+pub fn now_timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Interpreta timestamp ISO-8601/RFC 3339 (ex: `2020-07-10T12:34:56.000Z`)
+pub fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    DateTime::parse_from_rfc3339(s).map(|t| t.with_timezone(&Utc))
+}
+
+/// Interpreta limite da janela do resumo
+/// Parâmetro ausente ou vazio significa janela aberta
+pub fn parse_bound(
+    name: &str,
+    value: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, (StatusCode, String)> {
+    match value {
+        None | Some("") => Ok(None),
+        Some(v) => parse_timestamp(v)
+            .map(Some)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid {name}: {e}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Estatísticas locais com um pagamento de 1,00 em A para cada instante
+    fn stats(at: &[&str]) -> PaymentStats {
+        let mut stats = PaymentStats::default();
+        for t in at {
+            stats.add(UpstreamId::A, Money::from_cents(100), t);
+        }
+        stats
+    }
+
+    /// Pagamentos em A dentro da janela (limites como na query string)
+    fn count(stats: &PaymentStats, from: Option<&str>, to: Option<&str>) -> u64 {
+        let from = parse_bound("from", from).unwrap();
+        let to = parse_bound("to", to).unwrap();
+        stats.summary(from, to).unwrap().default.total_requests
+    }
+
+    #[test]
+    fn window_bounds_are_inclusive() {
+        let s = stats(&[
+            "2025-07-15T12:00:00.000Z",
+            "2025-07-15T12:00:01.000Z",
+            "2025-07-15T12:00:02.000Z",
+        ]);
+        let (from, to) = (
+            Some("2025-07-15T12:00:00.000Z"),
+            Some("2025-07-15T12:00:02.000Z"),
+        );
+        assert_eq!(count(&s, from, to), 3);
+
+        let summary = s.summary(parse_bound("from", from).unwrap(), None).unwrap();
+        assert_eq!(summary.default.total_amount, Money::from_cents(300));
+        assert_eq!(summary.fallback.total_requests, 0);
+    }
+
+    #[test]
+    fn window_bounds_have_millisecond_precision() {
+        let s = stats(&[
+            "2025-07-15T12:00:00.999Z",
+            "2025-07-15T12:00:01.000Z",
+            "2025-07-15T12:00:01.001Z",
+        ]);
+        let at = |t: &'static str| (Some(t), Some(t));
+
+        let (from, to) = at("2025-07-15T12:00:01.000Z");
+        assert_eq!(count(&s, from, to), 1);
+        assert_eq!(
+            count(
+                &s,
+                Some("2025-07-15T12:00:00.999Z"),
+                Some("2025-07-15T12:00:01.000Z")
+            ),
+            2
+        );
+        assert_eq!(count(&s, Some("2025-07-15T12:00:01.001Z"), None), 1);
+        // Mesmo instante em outro fuso e sem milissegundos explícitos
+        assert_eq!(
+            count(
+                &s,
+                Some("2025-07-15T09:00:01-03:00"),
+                Some("2025-07-15T12:00:01Z")
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn a_single_bound_leaves_the_other_side_open() {
+        let s = stats(&[
+            "2020-01-01T00:00:00.000Z",
+            "2025-07-15T12:00:00.000Z",
+            "2030-01-01T00:00:00.000Z",
+        ]);
+        assert_eq!(count(&s, Some("2025-07-15T12:00:00.000Z"), None), 2);
+        assert_eq!(count(&s, None, Some("2025-07-15T12:00:00.000Z")), 2);
+        assert_eq!(count(&s, Some(""), Some("")), 3);
+        assert_eq!(count(&s, None, None), 3);
+    }
+
+    #[test]
+    fn inverted_window_is_empty() {
+        let s = stats(&["2025-07-15T12:00:00.000Z"]);
+        assert_eq!(
+            count(
+                &s,
+                Some("2025-07-15T12:00:01.000Z"),
+                Some("2025-07-15T11:59:59.000Z")
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn malformed_bound_is_a_bad_request() {
+        for bad in [
+            "yesterday",
+            "2025-07-15",
+            "2025-07-15 12:00:00",
+            "1752582896000",
+        ] {
+            assert!(parse_timestamp(bad).is_err(), "{bad}");
+            let (status, msg) = parse_bound("from", Some(bad)).unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(msg.starts_with("invalid from"), "{msg}");
+        }
+    }
+}
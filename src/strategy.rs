@@ -45,7 +45,7 @@ impl RouteStrategy {
         // ========== ROUND-ROBIN ==========
         // Ambos circuit breakers fechados ou ambos abertos
         // Usa contador atômico para alternar uniformemente
-        self.skew.fetch_add(1, Ordering::Relaxed) % 2 == 0
+        self.skew.fetch_add(1, Ordering::Relaxed).is_multiple_of(2)
     }
 
     /// Registra quando o primário foi pulado devido a circuit breaker
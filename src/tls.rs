@@ -0,0 +1,512 @@
+/// TLS de saída para os processadores (backend reqwest)
+/// Monta a configuração rustls de cada processador: CA privada, certificado
+/// cliente (mTLS), SNI customizado e pinning do certificado do servidor
+use std::sync::Arc;
+
+use anyhow::Context;
+use reqwest::ClientBuilder;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+
+use crate::config::{HttpVersion, UpstreamTls, UpstreamTransport};
+use crate::upstream::UpstreamId;
+
+/// Aplica as opções TLS do processador ao cliente reqwest
+/// # Arguments
+/// * `base` - URL base do processador
+///
+/// # Returns
+/// * Builder configurado e URL base a usar (com o host trocado pelo SNI customizado)
+pub fn configure(
+    mut builder: ClientBuilder,
+    id: UpstreamId,
+    base: &str,
+    tcfg: &UpstreamTransport,
+) -> anyhow::Result<(ClientBuilder, String)> {
+    let tls = &tcfg.tls;
+    let mut url =
+        reqwest::Url::parse(base).with_context(|| format!("invalid upstream url {base}"))?;
+    if url.scheme() != "https" {
+        anyhow::ensure!(
+            !tls.is_custom() && tls.server_name.is_none(),
+            "upstream {id}: TLS options require an https:// url ({base})"
+        );
+        return Ok((builder, base.to_string()));
+    }
+
+    if tls.is_custom() {
+        builder = builder.use_preconfigured_tls(client_config(id, tls, tcfg.http_version)?);
+    }
+
+    // ========== SNI CUSTOMIZADO ==========
+    // reqwest usa o host da URL no SNI e no header Host: a URL passa a usar o
+    // nome desejado (o Host enviado também vira esse nome) e o resolver troca
+    // esse nome pelo host original, resolvido a cada conexão nova
+    if let Some(name) = &tls.server_name {
+        let host = url
+            .host_str()
+            .context("upstream url without host")?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        url.set_host(Some(name))
+            .with_context(|| format!("invalid TLS server name {name}"))?;
+        builder = builder.dns_resolver(Arc::new(SniResolver {
+            server_name: name.clone(),
+            host,
+        }));
+    }
+
+    Ok((builder, url.as_str().trim_end_matches('/').to_string()))
+}
+
+/// Resolve o nome do SNI customizado para o host original da URL
+/// Consultado a cada conexão nova: mudanças de DNS do host original valem
+/// sem reiniciar; outros nomes seguem a resolução normal
+struct SniResolver {
+    server_name: String,
+    host: String,
+}
+
+impl Resolve for SniResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = if name.as_str() == self.server_name {
+            self.host.clone()
+        } else {
+            name.as_str().to_string()
+        };
+        Box::pin(async move {
+            // Porta 0: o reqwest usa a porta da URL
+            let addrs = tokio::net::lookup_host((host.as_str(), 0)).await?;
+            Ok(Box::new(addrs.collect::<Vec<_>>().into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Configuração rustls do processador
+fn client_config(
+    id: UpstreamId,
+    tls: &UpstreamTls,
+    version: HttpVersion,
+) -> anyhow::Result<ClientConfig> {
+    // Provider explícito: o rustls do reqwest é compilado com mais de um
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    // ========== RAÍZES CONFIÁVEIS ==========
+    // CA privada substitui as raízes públicas (só ela é aceita)
+    let mut roots = RootCertStore::empty();
+    match &tls.ca_file {
+        Some(path) => {
+            for cert in read_certs(path)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("invalid CA certificate in {path}"))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let webpki =
+        WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&provider))
+            .build()
+            .context("build TLS verifier")?;
+    let verifier: Arc<dyn ServerCertVerifier> = if tls.pins.is_empty() {
+        webpki
+    } else {
+        Arc::new(PinnedVerifier {
+            upstream: id,
+            inner: webpki,
+            pins: tls.pins.clone(),
+        })
+    };
+
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+
+    // ========== CERTIFICADO CLIENTE (mTLS) ==========
+    let mut config = match (&tls.cert_file, &tls.key_file) {
+        (Some(cert), Some(key)) => {
+            let chain = read_certs(cert)?;
+            let key =
+                PrivateKeyDer::from_pem_file(key).with_context(|| format!("read TLS key {key}"))?;
+            builder
+                .with_client_auth_cert(chain, key)
+                .context("invalid TLS client certificate")?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+
+    // ALPN conforme a versão HTTP configurada
+    config.alpn_protocols = match version {
+        HttpVersion::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        HttpVersion::Http1 => vec![b"http/1.1".to_vec()],
+        HttpVersion::H2c => vec![b"h2".to_vec()],
+    };
+    Ok(config)
+}
+
+/// Lê todos os certificados de um arquivo PEM
+fn read_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .with_context(|| format!("read certificates {path}"))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("parse certificates {path}"))?;
+    anyhow::ensure!(!certs.is_empty(), "no certificates in {path}");
+    Ok(certs)
+}
+
+/// Verificação padrão (cadeia, validade e nome) mais o pinning do certificado
+/// do servidor: o SHA-256 do certificado (DER) precisa estar na lista
+#[derive(Debug)]
+struct PinnedVerifier {
+    upstream: UpstreamId,
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, end_entity.as_ref());
+        if self.pins.iter().any(|pin| pin[..] == *digest.as_ref()) {
+            Ok(verified)
+        } else {
+            metrics::counter!("upstream_tls_pin_mismatch", "upstream" => self.upstream.as_str())
+                .increment(1);
+            Err(rustls::Error::General(format!(
+                "upstream {} certificate does not match pinned SHA-256",
+                self.upstream
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use axum::Router;
+    use axum::http::HeaderMap;
+    use axum::routing::get;
+    use metrics::{
+        Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+    use rustls::ServerConfig;
+    use rustls::server::WebPkiClientVerifier;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsAcceptor;
+    use tokio_rustls::server::TlsStream;
+
+    use super::*;
+    use crate::config::Cfg;
+
+    /// Certificados gerados por testdata/tls/gen.sh
+    fn fixture(name: &str) -> String {
+        format!("{}/testdata/tls/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    /// SHA-256 (hex) do primeiro certificado do arquivo
+    fn pin_of(name: &str) -> String {
+        let cert = read_certs(&fixture(name)).unwrap().remove(0);
+        let digest = ring::digest::digest(&ring::digest::SHA256, cert.as_ref());
+        digest.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Listener TLS para o axum; guarda o SNI de cada handshake aceito
+    struct TlsListener {
+        tcp: TcpListener,
+        acceptor: TlsAcceptor,
+        sni: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl axum::serve::Listener for TlsListener {
+        type Io = TlsStream<TcpStream>;
+        type Addr = SocketAddr;
+
+        async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+            loop {
+                let Ok((tcp, addr)) = self.tcp.accept().await else {
+                    continue;
+                };
+                // Handshake recusado (ex: sem certificado cliente): próxima conexão
+                if let Ok(tls) = self.acceptor.accept(tcp).await {
+                    let sni = tls.get_ref().1.server_name().unwrap_or_default();
+                    self.sni.lock().unwrap().push(sni.to_string());
+                    return (tls, addr);
+                }
+            }
+        }
+
+        fn local_addr(&self) -> std::io::Result<Self::Addr> {
+            self.tcp.local_addr()
+        }
+    }
+
+    /// Processador HTTPS com certificado da CA privada (localhost e psp.internal)
+    struct Server {
+        port: u16,
+        sni: Arc<Mutex<Vec<String>>>,
+        hosts: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn server(require_client_cert: bool) -> Server {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = if require_client_cert {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(&fixture("ca.pem")).unwrap() {
+                roots.add(cert).unwrap();
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let key = PrivateKeyDer::from_pem_file(fixture("server.key")).unwrap();
+        let config = builder
+            .with_single_cert(read_certs(&fixture("server.pem")).unwrap(), key)
+            .unwrap();
+
+        let sni = Arc::new(Mutex::new(Vec::new()));
+        let hosts = Arc::new(Mutex::new(Vec::new()));
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = tcp.local_addr().unwrap().port();
+        let listener = TlsListener {
+            tcp,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            sni: Arc::clone(&sni),
+        };
+        let seen = Arc::clone(&hosts);
+        let app = Router::new().route(
+            "/",
+            get(move |headers: HeaderMap| async move {
+                let host = headers["host"].to_str().unwrap().to_string();
+                seen.lock().unwrap().push(host);
+                "ok"
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Server { port, sni, hosts }
+    }
+
+    /// GET no processador com as opções `UPSTREAM_A_TLS_*` dadas
+    async fn request(url: &str, tls: &[(&str, &str)]) -> anyhow::Result<()> {
+        let mut vars = vec![("UPSTREAM_A_URL", url), ("UPSTREAM_B_URL", url)];
+        vars.extend_from_slice(tls);
+        let cfg = Cfg::from_vars(&vars)?;
+        let builder = reqwest::Client::builder().use_rustls_tls();
+        let (builder, base) = configure(builder, UpstreamId::A, url, &cfg.upstream_a_transport)?;
+        builder
+            .build()?
+            .get(format!("{base}/"))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Recorder que só conta os counters, por nome
+    #[derive(Default)]
+    struct Counters(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+    impl Counters {
+        fn get(&self, name: &str) -> u64 {
+            self.0
+                .lock()
+                .unwrap()
+                .get(name)
+                .map_or(0, |c| c.load(Ordering::Relaxed))
+        }
+    }
+
+    impl Recorder for Counters {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let mut counters = self.0.lock().unwrap();
+            Counter::from_arc(Arc::clone(
+                counters.entry(key.name().to_string()).or_default(),
+            ))
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[tokio::test]
+    async fn private_ca_replaces_public_roots() {
+        let s = server(false).await;
+        let url = format!("https://localhost:{}", s.port);
+        let ca = fixture("ca.pem");
+
+        assert!(request(&url, &[]).await.is_err());
+        request(&url, &[("UPSTREAM_A_TLS_CA_FILE", &ca)])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn mtls_presents_client_certificate() {
+        let s = server(true).await;
+        let url = format!("https://localhost:{}", s.port);
+        let (ca, cert, key) = (
+            fixture("ca.pem"),
+            fixture("client.pem"),
+            fixture("client.key"),
+        );
+
+        assert!(
+            request(&url, &[("UPSTREAM_A_TLS_CA_FILE", &ca)])
+                .await
+                .is_err()
+        );
+        request(
+            &url,
+            &[
+                ("UPSTREAM_A_TLS_CA_FILE", &ca),
+                ("UPSTREAM_A_TLS_CERT_FILE", &cert),
+                ("UPSTREAM_A_TLS_KEY_FILE", &key),
+            ],
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn server_name_overrides_sni_and_host() {
+        let s = server(false).await;
+        // O certificado não tem IP: só passa validando como psp.internal
+        let url = format!("https://127.0.0.1:{}", s.port);
+        let ca = fixture("ca.pem");
+
+        assert!(
+            request(&url, &[("UPSTREAM_A_TLS_CA_FILE", &ca)])
+                .await
+                .is_err()
+        );
+        request(
+            &url,
+            &[
+                ("UPSTREAM_A_TLS_CA_FILE", &ca),
+                ("UPSTREAM_A_TLS_SERVER_NAME", "psp.internal"),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(s.sni.lock().unwrap().as_slice(), ["psp.internal"]);
+        assert_eq!(
+            s.hosts.lock().unwrap().as_slice(),
+            [format!("psp.internal:{}", s.port)]
+        );
+    }
+
+    #[tokio::test]
+    async fn pinned_certificate_is_accepted() {
+        let s = server(false).await;
+        let url = format!("https://localhost:{}", s.port);
+        let (ca, pin) = (fixture("ca.pem"), pin_of("server.pem"));
+        let recorder = Counters::default();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        request(
+            &url,
+            &[
+                ("UPSTREAM_A_TLS_CA_FILE", &ca),
+                (
+                    "UPSTREAM_A_TLS_PINS",
+                    &format!("{pin},{}", pin_of("ca.pem")),
+                ),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(recorder.get("upstream_tls_pin_mismatch"), 0);
+    }
+
+    #[tokio::test]
+    async fn pin_mismatch_fails_the_handshake() {
+        let s = server(false).await;
+        let url = format!("https://localhost:{}", s.port);
+        let (ca, pin) = (fixture("ca.pem"), pin_of("ca.pem"));
+        let recorder = Counters::default();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        assert!(
+            request(
+                &url,
+                &[
+                    ("UPSTREAM_A_TLS_CA_FILE", &ca),
+                    ("UPSTREAM_A_TLS_PINS", &pin)
+                ],
+            )
+            .await
+            .is_err()
+        );
+        assert_eq!(recorder.get("upstream_tls_pin_mismatch"), 1);
+        assert!(s.hosts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tls_options_require_https() {
+        let ca = fixture("ca.pem");
+        let err = request("http://localhost:1", &[("UPSTREAM_A_TLS_CA_FILE", &ca)])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("require an https:// url"));
+    }
+}
@@ -1,115 +1,775 @@
 /// Cliente HTTP otimizado para comunicação com processadores upstream
 /// Implementa connection pooling, timeouts e headers específicos da Rinha
-use std::{sync::Arc, time::Duration};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use reqwest::Client;
-use serde_json::Value;
+use anyhow::Context;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bytes::Bytes;
+use http::header::{AUTHORIZATION, CONTENT_TYPE};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
 
-use crate::config::Cfg;
+use crate::config::{Cfg, HttpVersion, UpstreamAuth, UpstreamMapping};
+use crate::dispatch::Payment;
+use crate::hyper_pool::HyperPool;
+use crate::retry_policy::{self, RetryPolicy};
+use crate::signing::{self, RequestSigner};
+use crate::tls;
+
+/// Tamanho máximo lido do corpo de uma resposta de sucesso
+const MAX_RESPONSE_BODY: usize = 64 * 1024;
+/// Tamanho máximo do corpo de erro guardado na mensagem
+const MAX_ERROR_BODY: usize = 512;
+
+/// Identidade de um processador upstream
+/// Serializado como `"A"`/`"B"` (formato já usado no WAL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UpstreamId {
+    /// Processador default
+    A,
+    /// Processador fallback
+    B,
+}
+
+impl UpstreamId {
+    /// Nome curto usado em logs e labels de métricas
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::A => "A",
+            Self::B => "B",
+        }
+    }
+}
+
+impl fmt::Display for UpstreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Falha de uma chamada ao processador
+#[derive(Debug, thiserror::Error)]
+pub enum UpstreamError {
+    /// Processador não respondeu dentro do timeout
+    #[error("upstream {upstream} timed out")]
+    Timeout { upstream: UpstreamId },
+    /// Conexão recusada ou falha antes de a requisição ser enviada
+    #[error("upstream {upstream} connection failed: {reason}")]
+    Connect {
+        upstream: UpstreamId,
+        reason: String,
+    },
+    /// Conexão perdida depois do envio (o processador pode ter registrado o pagamento)
+    #[error("upstream {upstream} connection interrupted: {reason}")]
+    Interrupted {
+        upstream: UpstreamId,
+        reason: String,
+    },
+    /// Processador respondeu com status de erro (corpo limitado a MAX_ERROR_BODY)
+    #[error("upstream {upstream} returned {status}: {body}")]
+    Http {
+        upstream: UpstreamId,
+        status: StatusCode,
+        body: String,
+    },
+    /// Tentativa abortada antes de terminar (ex: perdeu o hedge)
+    #[error("upstream {upstream} request cancelled")]
+    Cancelled { upstream: UpstreamId },
+}
+
+impl UpstreamError {
+    /// Processador que falhou
+    pub fn upstream(&self) -> UpstreamId {
+        match self {
+            Self::Timeout { upstream }
+            | Self::Connect { upstream, .. }
+            | Self::Interrupted { upstream, .. }
+            | Self::Http { upstream, .. }
+            | Self::Cancelled { upstream } => *upstream,
+        }
+    }
+
+    /// Status HTTP devolvido ao nosso cliente
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            Self::Connect { .. } | Self::Interrupted { .. } => StatusCode::BAD_GATEWAY,
+            Self::Http { status, .. } => *status,
+            Self::Cancelled { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Rótulo curto para métricas
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Timeout { .. } => "timeout",
+            Self::Connect { .. } => "connect",
+            Self::Interrupted { .. } => "interrupted",
+            Self::Http { .. } => "http",
+            Self::Cancelled { .. } => "cancelled",
+        }
+    }
+
+    /// Falha transitória que pode ser repetida imediatamente
+    /// Só entram falhas em que o pagamento com certeza não foi registrado:
+    /// conexão não estabelecida e status de rejeição sem processamento (429/503).
+    /// Timeouts, conexões interrompidas após o envio e demais 5xx ficam de fora:
+    /// o processador pode ter registrado o pagamento, e o retry com o mesmo
+    /// correlationId voltaria como duplicata (4xx), contada como falha
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Connect { .. } => true,
+            Self::Http { status, .. } => retry_policy::is_retryable_status(*status),
+            _ => false,
+        }
+    }
+
+    /// Falha que indica processador com problema (alimenta o circuit breaker)
+    /// Rejeições 4xx são do pagamento, não do processador; cancelamentos são nossos
+    pub fn is_upstream_fault(&self) -> bool {
+        match self {
+            Self::Http { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::Cancelled { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// Falha de desfecho incerto: o processador pode ter registrado o pagamento
+    /// (timeout, conexão interrompida após o envio, 5xx que não seja 503)
+    /// Uma nova tentativa no mesmo processador pode voltar como duplicata (4xx)
+    pub fn maybe_processed(&self) -> bool {
+        self.is_upstream_fault() && !self.is_retryable()
+    }
+}
+
+/// Resposta de sucesso do processador (mensagem lida pelo pointer configurado)
+#[derive(Clone, Debug)]
+pub struct ProcessorResponse {
+    /// Mensagem de confirmação do processador
+    /// `Err` quando o 2xx não trouxe uma legível: o pagamento conta como processado
+    pub message: Result<String, DecodeError>,
+}
+
+/// Corpo de um 2xx que não pôde ser lido
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    /// Corpo maior que MAX_RESPONSE_BODY
+    #[error("body too large")]
+    TooLarge,
+    /// Corpo não é JSON
+    #[error("invalid JSON: {0}")]
+    InvalidJson(String),
+    /// JSON sem string não vazia no pointer configurado
+    #[error("{0}")]
+    MissingMessage(String),
+}
+
+impl DecodeError {
+    /// Rótulo curto para métricas
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::TooLarge => "too_large",
+            Self::InvalidJson(_) => "invalid_json",
+            Self::MissingMessage(_) => "missing_message",
+        }
+    }
+}
+
+/// Backend HTTP usado nas chamadas aos processadores
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpstreamBackend {
+    /// Cliente reqwest de uso geral (default)
+    Reqwest,
+    /// Pool HTTP/1.1 próprio sobre hyper, pré-aquecido e com template pré-serializado
+    Hyper,
+}
+
+impl UpstreamBackend {
+    /// Interpreta `UPSTREAM_BACKEND` (`reqwest` ou `hyper`)
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reqwest" => Some(Self::Reqwest),
+            "hyper" => Some(Self::Hyper),
+            _ => None,
+        }
+    }
+
+    /// Nome usado em logs e labels de métricas
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Reqwest => "reqwest",
+            Self::Hyper => "hyper",
+        }
+    }
+}
+
+/// Resposta crua do processador (corpo limitado por `body_limit`)
+pub struct RawResponse {
+    pub status: StatusCode,
+    pub body: Vec<u8>,
+    /// Corpo excedia o limite e foi cortado
+    pub truncated: bool,
+}
+
+/// Requisição pronta para envio: corpo final e headers da tentativa
+/// (assinatura), somados aos headers fixos do transporte
+pub struct Outbound {
+    pub body: Bytes,
+    pub headers: HeaderMap,
+}
+
+/// Limite de leitura do corpo conforme o status
+pub fn body_limit(status: StatusCode) -> usize {
+    if status.is_success() {
+        MAX_RESPONSE_BODY
+    } else {
+        MAX_ERROR_BODY
+    }
+}
+
+/// Transporte HTTP de um cliente upstream
+enum Transport {
+    /// reqwest com pool próprio e URL já montada
+    Reqwest {
+        http: Client,
+        /// URL base do processador (já ajustada para SNI customizado)
+        base: String,
+        url: String,
+        /// URL usada só para abrir conexões no warm-up
+        warm_url: String,
+        headers: HeaderMap,
+    },
+    /// Pool hyper dedicado ao processador
+    Hyper(Box<HyperPool>),
+}
 
 /// Cliente HTTP para comunicação com processadores de pagamento
 /// Mantém pool de conexões e configurações otimizadas para alta performance
 pub struct UpstreamClient {
-    /// Nome identificador do processador (A ou B)
-    pub name: String,
-    /// Cliente HTTP com pool de conexões compartilhado
-    http: Arc<Client>,
+    /// Processador atendido por este cliente
+    pub id: UpstreamId,
+    /// Backend selecionado no startup
+    backend: UpstreamBackend,
+    /// Versão HTTP (label das métricas de streams)
+    http_version: HttpVersion,
+    /// Transporte com pool de conexões compartilhado
+    transport: Arc<Transport>,
+    /// Política de retry (orçamento global compartilhado entre processadores)
+    retry: Arc<RetryPolicy>,
+    /// Assinatura das requisições (quando o processador exige)
+    signer: Option<Arc<dyn RequestSigner>>,
+    /// Path assinado (path e query da URL de pagamento)
+    sign_path: Arc<str>,
+    /// URL base configurada (monta as URLs das consultas administrativas)
+    base: Arc<str>,
+    /// Template do corpo e pointer da mensagem de confirmação
+    mapping: Arc<UpstreamMapping>,
 }
 
 impl Clone for UpstreamClient {
     /// Implementação customizada de Clone para compartilhar o pool HTTP
     fn clone(&self) -> Self {
         Self {
-            name: self.name.clone(),
-            http: Arc::clone(&self.http),
+            id: self.id,
+            backend: self.backend,
+            http_version: self.http_version,
+            transport: Arc::clone(&self.transport),
+            retry: Arc::clone(&self.retry),
+            signer: self.signer.clone(),
+            sign_path: Arc::clone(&self.sign_path),
+            base: Arc::clone(&self.base),
+            mapping: Arc::clone(&self.mapping),
         }
     }
 }
 
 impl UpstreamClient {
     /// Cria novo cliente upstream com configurações otimizadas
+    /// Conexões são abertas depois, pelo warm-up (`warm`)
     /// # Arguments
-    /// * `name` - Nome do processador (A ou B)
+    /// * `id` - Processador (A ou B)
     /// * `cfg` - Configurações globais da aplicação
-    pub async fn new(name: String, cfg: &Cfg) -> anyhow::Result<Self> {
-        // ========== CONFIGURAÇÕES DE PERFORMANCE ==========
-        // HTTP/1.1 only para compatibilidade com servidores legacy
-        // Pool de conexões agressivo para reduzir latência
-        let http = Client::builder()
-            .pool_max_idle_per_host(32) // Pool grande para alta concorrência
-            .pool_idle_timeout(Duration::from_secs(30)) // Keep-alive por 30s
-            .tcp_nodelay(true) // Desabilita Nagle para baixa latência
-            .use_rustls_tls() // TLS otimizado
-            .connect_timeout(Duration::from_millis(25)) // Timeout de conexão curto
-            .timeout(Duration::from_millis(cfg.request_timeout_ms)) // Timeout total da requisição
-            .build()?;
+    /// * `retry` - Política de retry compartilhada
+    pub async fn new(id: UpstreamId, cfg: &Cfg, retry: Arc<RetryPolicy>) -> anyhow::Result<Self> {
+        // ========== URL E CREDENCIAIS ==========
+        let (base, auth, signing, mapping, tcfg) = match id {
+            UpstreamId::A => (
+                &cfg.upstream_a,
+                &cfg.upstream_a_auth,
+                &cfg.upstream_a_signing,
+                &cfg.upstream_a_mapping,
+                &cfg.upstream_a_transport,
+            ),
+            UpstreamId::B => (
+                &cfg.upstream_b,
+                &cfg.upstream_b_auth,
+                &cfg.upstream_b_signing,
+                &cfg.upstream_b_mapping,
+                &cfg.upstream_b_transport,
+            ),
+        };
+        let headers = auth_headers(auth)?;
+        let signer = signing::from_cfg(signing)
+            .with_context(|| format!("upstream {id}: invalid signing config"))?;
+        let pay_url = format!("{base}{}", cfg.pay_path);
+        let pay_url = reqwest::Url::parse(&pay_url)
+            .with_context(|| format!("invalid upstream url {pay_url}"))?;
+        let sign_path: Arc<str> = match pay_url.query() {
+            Some(query) => format!("{}?{query}", pay_url.path()).into(),
+            None => pay_url.path().into(),
+        };
+
+        let transport = match cfg.upstream_backend {
+            UpstreamBackend::Reqwest => {
+                // ========== CONFIGURAÇÕES DE PERFORMANCE ==========
+                // Pool, timeouts e protocolo configurados por processador
+                let idle = if tcfg.keepalive { tcfg.pool_size } else { 0 };
+                let mut builder = Client::builder()
+                    .pool_max_idle_per_host(idle) // Sem keep-alive nenhuma conexão fica ociosa
+                    .pool_idle_timeout(tcfg.pool_idle_timeout)
+                    .tcp_nodelay(tcfg.tcp_nodelay)
+                    .use_rustls_tls() // TLS otimizado
+                    .connect_timeout(tcfg.connect_timeout)
+                    .timeout(tcfg.request_timeout); // Timeout total da requisição
+                if let Some(path) = &tcfg.unix_socket {
+                    builder = builder.unix_socket(path.as_str());
+                }
+                builder = match tcfg.http_version {
+                    HttpVersion::Auto => builder,
+                    HttpVersion::Http1 => builder.http1_only(),
+                    // Uma conexão por processador, com todas as requisições multiplexadas
+                    HttpVersion::H2c => builder.http2_prior_knowledge(),
+                };
+                // CA, certificado cliente, SNI e pinning (URLs https://)
+                let (builder, base) = tls::configure(builder, id, base, tcfg)?;
+                let http = builder.build()?;
+                Transport::Reqwest {
+                    http,
+                    base: base.clone(),
+                    url: format!("{base}{}", cfg.pay_path),
+                    warm_url: format!("{base}{}", cfg.upstream_warm_path),
+                    headers,
+                }
+            }
+            UpstreamBackend::Hyper => {
+                anyhow::ensure!(
+                    !tcfg.tls.is_custom() && tcfg.tls.server_name.is_none(),
+                    "upstream {id}: TLS options require the reqwest backend"
+                );
+                let url = format!("{base}{}", cfg.pay_path);
+                Transport::Hyper(Box::new(HyperPool::new(id, &url, headers, tcfg)?))
+            }
+        };
 
         Ok(Self {
-            name,
-            http: Arc::new(http),
+            id,
+            backend: cfg.upstream_backend,
+            http_version: tcfg.http_version,
+            transport: Arc::new(transport),
+            retry,
+            signer,
+            sign_path,
+            base: base.as_str().into(),
+            mapping: Arc::new(mapping.clone()),
         })
     }
 
+    /// Abre até `n` conexões keep-alive com o processador
+    /// Falhas não são erro: processador fora do ar só fica sem conexões prontas
+    /// # Returns
+    /// * Quantidade de conexões prontas
+    pub async fn warm(&self, n: usize) -> usize {
+        match &*self.transport {
+            Transport::Reqwest {
+                http,
+                warm_url,
+                headers,
+                ..
+            } => {
+                // reqwest não expõe o pool: requisições simultâneas abrem uma
+                // conexão cada, que fica ociosa no pool ao terminar
+                // (em h2c todas dividem a mesma conexão, basta uma)
+                let n = match self.http_version {
+                    HttpVersion::H2c => n.min(1),
+                    HttpVersion::Auto | HttpVersion::Http1 => n,
+                };
+                let probes = (0..n).map(|_| async {
+                    let resp = http.get(warm_url).headers(headers.clone()).send().await;
+                    match resp {
+                        // Corpo consumido para a conexão voltar ao pool
+                        Ok(resp) => resp.bytes().await.is_ok(),
+                        Err(e) => {
+                            debug!("upstream {}: warm-up request failed: {e}", self.id);
+                            false
+                        }
+                    }
+                });
+                futures::future::join_all(probes)
+                    .await
+                    .into_iter()
+                    .filter(|ok| *ok)
+                    .count()
+            }
+            Transport::Hyper(pool) => pool.warm(n).await,
+        }
+    }
+
     /// Executa requisição HTTP para o processador upstream
     /// # Arguments
-    /// * `cfg` - Configurações da aplicação (Arc para compartilhamento)
-    /// * `body` - Payload JSON da requisição
+    /// * `payment` - Pagamento a enviar
     ///
     /// # Returns
-    /// * `Ok((nome, resposta))` - Sucesso com nome do processador e resposta JSON
-    /// * `Err((nome, status, mensagem))` - Erro com detalhes para circuit breaker
+    /// * `Ok((processador, resposta))` - Sucesso com resposta validada
+    /// * `Err(erro)` - Falha tipada (processador, tipo e detalhes)
     pub async fn request(
         &self,
-        cfg: Arc<Cfg>,
-        body: Value,
-    ) -> Result<(String, Value), (String, http::StatusCode, String)> {
-        // ========== CONSTRUÇÃO DA URL ==========
-        // Seleciona URL base baseado no nome do processador
-        let base = if self.name == "A" {
-            &cfg.upstream_a
-        } else {
-            &cfg.upstream_b
+        payment: &Payment,
+    ) -> Result<(UpstreamId, ProcessorResponse), UpstreamError> {
+        // ========== RETRY ==========
+        // Falhas transitórias são repetidas com backoff, dentro do orçamento global
+        self.retry.on_request();
+        let mut attempt = 1;
+        loop {
+            match self.send(payment).await {
+                Ok(res) => return Ok(res),
+                Err(e) => {
+                    if !self.retry.should_retry(self.id, attempt, e.is_retryable()) {
+                        return Err(e);
+                    }
+                    tokio::time::sleep(self.retry.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Executa uma única tentativa
+    async fn send(
+        &self,
+        payment: &Payment,
+    ) -> Result<(UpstreamId, ProcessorResponse), UpstreamError> {
+        // Corpo serializado e assinado a cada tentativa (timestamp atual)
+        let mut out = Outbound {
+            body: self.mapping.request.render(payment),
+            headers: HeaderMap::new(),
+        };
+        if let Some(signer) = &self.signer {
+            signer.sign(&Method::POST, &self.sign_path, &out.body, &mut out.headers);
+        }
+
+        let started = Instant::now();
+        let _stream = StreamGauge::open(self.id, self.http_version);
+        let raw = match &*self.transport {
+            Transport::Reqwest {
+                http, url, headers, ..
+            } => {
+                let req = http
+                    .post(url)
+                    .headers(headers.clone())
+                    .headers(out.headers.clone())
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(out.body.clone());
+                self.exchange_reqwest(req).await
+            }
+            Transport::Hyper(pool) => pool.send(&out).await,
+        };
+        metrics::histogram!("upstream_latency_ms", "upstream" => self.id.as_str(), "backend" => self.backend.as_str())
+            .record(started.elapsed().as_secs_f64() * 1000.0);
+
+        self.interpret(raw?)
+    }
+
+    /// Consulta GET ao processador pelo mesmo transporte dos pagamentos
+    /// (TLS, socket Unix, credenciais e assinatura), sem retry
+    /// # Arguments
+    /// * `path` - Path e query relativos à URL base do processador
+    /// * `extra` - Headers da consulta (sobrepõem os de credenciais)
+    /// * `timeout` - Prazo total da consulta
+    pub async fn get(
+        &self,
+        path: &str,
+        extra: &HeaderMap,
+        timeout: Duration,
+    ) -> Result<RawResponse, UpstreamError> {
+        let url = format!("{}{path}", self.base);
+        let url = reqwest::Url::parse(&url).map_err(|e| UpstreamError::Connect {
+            upstream: self.id,
+            reason: format!("invalid url {url}: {e}"),
+        })?;
+        let path_and_query = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
         };
-        let url = format!("{base}{}", cfg.pay_path);
 
-        // ========== PREPARAÇÃO DA REQUISIÇÃO ==========
-        // POST com JSON body e headers específicos da Rinha
-        let mut req = self.http.post(&url).json(&body);
-        req = req.header("X-Rinha-Token", "123"); // Token obrigatório para processadores oficiais
+        let mut out = Outbound {
+            body: Bytes::new(),
+            headers: extra.clone(),
+        };
+        if let Some(signer) = &self.signer {
+            signer.sign(&Method::GET, &path_and_query, &out.body, &mut out.headers);
+        }
+
+        match &*self.transport {
+            Transport::Reqwest {
+                http,
+                base,
+                headers,
+                ..
+            } => {
+                let req = http
+                    .get(format!("{base}{path}"))
+                    .headers(headers.clone())
+                    .headers(out.headers)
+                    .timeout(timeout);
+                self.exchange_reqwest(req).await
+            }
+            Transport::Hyper(pool) => pool.get(&path_and_query, &out, timeout).await,
+        }
+    }
 
+    /// Tentativa via reqwest
+    async fn exchange_reqwest(&self, req: RequestBuilder) -> Result<RawResponse, UpstreamError> {
         // ========== EXECUÇÃO DA REQUISIÇÃO ==========
-        match req.send().await {
-            Ok(resp) => {
-                let sc = resp.status();
-
-                // ========== TRATAMENTO DE SUCESSO ==========
-                if sc.is_success() {
-                    // Para mock, simula resposta de sucesso da Rinha
-                    // Em produção, faria resp.json().await
-                    Ok((
-                        self.name.clone(),
-                        serde_json::json!({
-                            "message": "payment processed successfully"
-                        }),
-                    ))
-                } else {
-                    // ========== TRATAMENTO DE ERRO HTTP ==========
-                    Err((
-                        self.name.clone(),
-                        sc,
-                        format!("upstream {} returned {}", self.name, sc),
-                    ))
+        // Só falhas ao conectar garantem que nada foi enviado; o resto
+        // (reset no meio da troca, corpo interrompido) é `Interrupted`
+        let network = |e: reqwest::Error| {
+            let reason = e.to_string();
+            if e.is_timeout() {
+                UpstreamError::Timeout { upstream: self.id }
+            } else if e.is_connect() {
+                UpstreamError::Connect {
+                    upstream: self.id,
+                    reason,
+                }
+            } else {
+                UpstreamError::Interrupted {
+                    upstream: self.id,
+                    reason,
                 }
             }
-            Err(e) => {
-                // ========== TRATAMENTO DE ERRO DE REDE ==========
-                // Connection timeout, DNS failure, etc.
-                Err((
-                    self.name.clone(),
-                    http::StatusCode::BAD_GATEWAY,
-                    format!("upstream {} error: {e}", self.name),
-                ))
+        };
+        let mut resp = req.send().await.map_err(network)?;
+        let status = resp.status();
+        let (body, truncated) = read_limited(&mut resp, body_limit(status))
+            .await
+            .map_err(network)?;
+        Ok(RawResponse {
+            status,
+            body,
+            truncated,
+        })
+    }
+
+    /// Mensagem de confirmação lida pelo pointer configurado
+    fn message(&self, raw: &RawResponse) -> Result<String, DecodeError> {
+        if raw.truncated {
+            return Err(DecodeError::TooLarge);
+        }
+        let body: serde_json::Value = serde_json::from_slice(&raw.body)
+            .map_err(|e| DecodeError::InvalidJson(e.to_string()))?;
+        let pointer = &self.mapping.message_pointer;
+        match body.pointer(pointer) {
+            Some(serde_json::Value::String(message)) if !message.trim().is_empty() => {
+                Ok(message.clone())
+            }
+            Some(serde_json::Value::String(_)) => {
+                Err(DecodeError::MissingMessage(format!("{pointer} is empty")))
             }
+            Some(_) => Err(DecodeError::MissingMessage(format!(
+                "{pointer} is not a string"
+            ))),
+            None => Err(DecodeError::MissingMessage(format!("missing {pointer}"))),
+        }
+    }
+
+    /// Valida a resposta crua (comum aos backends)
+    fn interpret(
+        &self,
+        raw: RawResponse,
+    ) -> Result<(UpstreamId, ProcessorResponse), UpstreamError> {
+        // ========== TRATAMENTO DE SUCESSO ==========
+        // 2xx é sempre sucesso: o processador aceitou o pagamento. Corpo
+        // inválido fica marcado na resposta (sem mensagem) e é contado
+        if raw.status.is_success() {
+            let message = self.message(&raw);
+            if let Err(e) = &message {
+                warn!("upstream {}: unreadable success body: {e}", self.id);
+                metrics::counter!("upstream_decode_err", "upstream" => self.id.as_str(), "reason" => e.kind())
+                    .increment(1);
+            }
+            return Ok((self.id, ProcessorResponse { message }));
+        }
+
+        // ========== TRATAMENTO DE ERRO HTTP ==========
+        // Corpo (limitado) vai no erro para diagnóstico
+        let text = String::from_utf8_lossy(&raw.body);
+        let ellipsis = if raw.truncated { "..." } else { "" };
+        Err(UpstreamError::Http {
+            upstream: self.id,
+            status: raw.status,
+            body: format!("{}{ellipsis}", text.trim()),
+        })
+    }
+}
+
+/// Requisições em andamento por processador (streams, no h2c)
+/// Comparado com `upstream_connections` mostra a multiplexação; o decremento
+/// no Drop cobre tentativas abortadas (hedge perdido, timeout)
+struct StreamGauge(metrics::Gauge);
+
+impl StreamGauge {
+    fn open(upstream: UpstreamId, version: HttpVersion) -> Self {
+        let gauge = metrics::gauge!("upstream_streams", "upstream" => upstream.as_str(), "protocol" => version.as_str());
+        gauge.increment(1.0);
+        Self(gauge)
+    }
+}
+
+impl Drop for StreamGauge {
+    fn drop(&mut self) {
+        self.0.decrement(1.0);
+    }
+}
+
+/// Headers de credenciais enviados em toda requisição ao processador
+fn auth_headers(auth: &UpstreamAuth) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    match auth {
+        UpstreamAuth::None => {}
+        UpstreamAuth::Header { name, value } => {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
         }
+        UpstreamAuth::Bearer(token) => {
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {token}"))?,
+            );
+        }
+        UpstreamAuth::Basic { user, password } => {
+            let raw = format!("{user}:{}", password.as_deref().unwrap_or(""));
+            let encoded = BASE64_STANDARD.encode(raw);
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Basic {encoded}"))?,
+            );
+        }
+    }
+    // Valores sensíveis ficam fora de logs de debug do hyper/reqwest
+    for value in headers.values_mut() {
+        value.set_sensitive(true);
+    }
+    Ok(headers)
+}
+
+/// Lê no máximo `limit` bytes do corpo da resposta
+/// # Returns
+/// * `(bytes, truncado)` - `truncado` indica que o corpo excedia o limite
+async fn read_limited(resp: &mut Response, limit: usize) -> reqwest::Result<(Vec<u8>, bool)> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        let room = limit - buf.len();
+        if chunk.len() > room {
+            buf.extend_from_slice(&chunk[..room]);
+            return Ok((buf, true));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok((buf, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::Router;
+    use axum::extract::State;
+    use axum::routing::post;
+
+    use super::*;
+    use crate::money::Money;
+
+    /// Processador falso que responde sempre o mesmo status e corpo
+    async fn fake(status: u16, body: String) -> UpstreamClient {
+        let reply = Arc::new(Mutex::new((StatusCode::from_u16(status).unwrap(), body)));
+        let app = Router::new()
+            .route(
+                "/payments",
+                post(
+                    |State(reply): State<Arc<Mutex<(StatusCode, String)>>>| async move {
+                        reply.lock().unwrap().clone()
+                    },
+                ),
+            )
+            .with_state(reply);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let cfg = Cfg::from_vars(&[
+            ("UPSTREAM_A_URL", &url),
+            ("UPSTREAM_B_URL", &url),
+            ("UPSTREAM_PAY_PATH", "/payments"),
+            ("UPSTREAM_RETRY_MAX_ATTEMPTS", "1"),
+        ])
+        .unwrap();
+        let retry = Arc::new(RetryPolicy::new(&cfg));
+        UpstreamClient::new(UpstreamId::A, &cfg, retry)
+            .await
+            .unwrap()
+    }
+
+    async fn request(status: u16, body: String) -> Result<ProcessorResponse, UpstreamError> {
+        let up = fake(status, body).await;
+        let payment = Payment::new(Money::from_cents(1990));
+        up.request(&payment).await.map(|(_, resp)| resp)
+    }
+
+    #[tokio::test]
+    async fn success_body_yields_the_message() {
+        let resp = request(200, r#"{"message":"ok"}"#.into()).await.unwrap();
+        assert_eq!(resp.message, Ok("ok".into()));
+    }
+
+    #[tokio::test]
+    async fn unreadable_success_body_is_marked_not_replaced() {
+        let resp = request(200, "not json".into()).await.unwrap();
+        assert!(matches!(resp.message, Err(DecodeError::InvalidJson(_))));
+
+        let resp = request(200, r#"{"message":""}"#.into()).await.unwrap();
+        assert!(matches!(resp.message, Err(DecodeError::MissingMessage(_))));
+
+        let resp = request(200, r#"{"status":"ok"}"#.into()).await.unwrap();
+        assert!(matches!(resp.message, Err(DecodeError::MissingMessage(_))));
+
+        // Corpo acima do limite nem chega a ser interpretado
+        let big = format!(r#"{{"message":"{}"}}"#, "x".repeat(MAX_RESPONSE_BODY));
+        let resp = request(200, big).await.unwrap();
+        assert_eq!(resp.message, Err(DecodeError::TooLarge));
+    }
+
+    #[tokio::test]
+    async fn oversized_error_body_is_truncated() {
+        let err = request(500, "e".repeat(10 * MAX_ERROR_BODY))
+            .await
+            .unwrap_err();
+        let UpstreamError::Http { status, body, .. } = err else {
+            panic!("expected Http error, got {err:?}");
+        };
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body, format!("{}...", "e".repeat(MAX_ERROR_BODY)));
+
+        let err = request(422, "duplicate".into()).await.unwrap_err();
+        assert!(matches!(err, UpstreamError::Http { body, .. } if body == "duplicate"));
     }
 }
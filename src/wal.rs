@@ -0,0 +1,617 @@
+/// Write-ahead log (WAL) de pagamentos aceitos
+/// Arquivo append-only com um registro JSON por linha, reaplicado no startup
+/// para reconstruir as estatísticas, os saldos dos clientes e retomar
+/// pagamentos não finalizados
+///
+/// A escrita fica numa thread dedicada: `append` só serializa o registro e o
+/// envia por um canal, sem I/O nas threads do tokio. A thread grava tudo o que
+/// estiver na fila num único write e agrupa os fsyncs (group commit); no modo
+/// `always` quem gravou espera o fsync do seu lote antes de responder
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
+
+use crate::accounts::{HISTORY_LEN, Transacao};
+use crate::money::Money;
+use crate::upstream::UpstreamId;
+
+/// Limite de bytes gravados por lote
+const MAX_BATCH_BYTES: usize = 256 * 1024;
+
+/// Política de fsync do WAL
+#[derive(Clone, Debug)]
+pub enum FsyncPolicy {
+    /// fsync após cada lote gravado (registros que chegam durante um fsync
+    /// entram no lote seguinte); `append` só termina após o fsync do registro
+    Always,
+    /// fsync periódico em background (crash do host perde até um intervalo)
+    Interval(Duration),
+    /// Nunca chama fsync (sobrevive a crash do processo, não do host)
+    Never,
+}
+
+impl FsyncPolicy {
+    /// Interpreta a política a partir do modo configurado
+    /// # Arguments
+    /// * `mode` - `always`, `interval` ou `never`
+    /// * `interval_ms` - Intervalo usado no modo `interval`
+    pub fn parse(mode: &str, interval_ms: u64) -> anyhow::Result<Self> {
+        match mode {
+            "always" => Ok(Self::Always),
+            "interval" => Ok(Self::Interval(Duration::from_millis(interval_ms))),
+            "never" => Ok(Self::Never),
+            other => anyhow::bail!("invalid WAL_FSYNC: {other}"),
+        }
+    }
+}
+
+/// Registro do WAL
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "t", rename_all = "snake_case")]
+pub enum WalRecord {
+    /// Pagamento aceito, ainda sem resultado do processador
+    Accepted {
+        id: String,
+        amount: Money,
+        requested_at: String,
+    },
+    /// Pagamento confirmado por um processador
+    Processed {
+        id: String,
+        processor: UpstreamId,
+        amount: Money,
+        requested_at: String,
+    },
+    /// Pagamento definitivamente perdido (não será retomado)
+    Failed { id: String },
+    /// Reset das estatísticas via /purge-payments
+    Purge,
+    /// Transação de cliente confirmada
+    Transacao { cliente: i64, transacao: Transacao },
+    /// Snapshot da conta gravado na compactação (substitui as transações anteriores)
+    Conta {
+        cliente: i64,
+        saldo: i64,
+        /// Últimas transações, mais recente primeiro
+        historico: Vec<Transacao>,
+    },
+}
+
+/// Estado reconstruído de uma conta
+#[derive(Default)]
+pub struct ContaSnapshot {
+    /// Saldo em centavos
+    pub saldo: i64,
+    /// Últimas transações, mais recente primeiro (até `HISTORY_LEN`)
+    pub historico: Vec<Transacao>,
+}
+
+/// Resultado da reaplicação do WAL
+#[derive(Default)]
+pub struct Replay {
+    /// Pagamentos confirmados: (processador, valor, requestedAt)
+    pub processed: Vec<(UpstreamId, Money, String)>,
+    /// Pagamentos aceitos sem resultado: (id, valor, requestedAt)
+    pub pending: Vec<(String, Money, String)>,
+    /// Contas com transações confirmadas
+    pub contas: BTreeMap<i64, ContaSnapshot>,
+}
+
+/// Linha enviada à thread de escrita
+struct Line {
+    bytes: Vec<u8>,
+    /// Avisado após o fsync do lote da linha (só no modo `always`)
+    synced: Option<oneshot::Sender<()>>,
+}
+
+/// Registro enfileirado no WAL
+/// No modo `always`, `synced` só termina depois que o registro chegou ao disco
+#[must_use = "await synced() before acknowledging the request"]
+pub struct Appended(Option<oneshot::Receiver<()>>);
+
+impl Appended {
+    /// Espera o fsync do registro (imediato fora do modo `always`)
+    pub async fn synced(self) {
+        if let Some(rx) = self.0
+            && rx.await.is_err()
+        {
+            // Escrita ou fsync falhou (já logado pela thread) ou ela parou
+            error!("wal: record not confirmed on disk");
+            metrics::counter!("wal_errors").increment(1);
+        }
+    }
+}
+
+/// Write-ahead log append-only
+pub struct Wal {
+    /// Fila de linhas para a thread de escrita
+    tx: Option<Sender<Line>>,
+    /// `append` espera o fsync (modo `always`)
+    wait_sync: bool,
+    /// Thread de escrita (encerrada e aguardada no Drop)
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Wal {
+    /// Abre o WAL, reaplica os registros existentes e compacta o arquivo
+    /// # Returns
+    /// * WAL pronto para append e o estado reconstruído
+    pub fn open(path: &str, policy: FsyncPolicy) -> anyhow::Result<(Self, Replay)> {
+        let path = PathBuf::from(path);
+        let replay = Self::replay(&path)?;
+
+        // ========== COMPACTAÇÃO ==========
+        // Reescreve só o necessário e troca o arquivo atomicamente
+        let tmp = path.with_extension("compact");
+        {
+            let mut out = File::create(&tmp).context("create compacted WAL")?;
+            // Pagamentos confirmados não precisam mais do id
+            for (processor, amount, requested_at) in &replay.processed {
+                write_record(
+                    &mut out,
+                    &WalRecord::Processed {
+                        id: String::new(),
+                        processor: *processor,
+                        amount: *amount,
+                        requested_at: requested_at.clone(),
+                    },
+                )?;
+            }
+            for (id, amount, requested_at) in &replay.pending {
+                write_record(
+                    &mut out,
+                    &WalRecord::Accepted {
+                        id: id.clone(),
+                        amount: *amount,
+                        requested_at: requested_at.clone(),
+                    },
+                )?;
+            }
+            // Contas viram um snapshot cada: o arquivo não cresce com o
+            // número de transações
+            for (cliente, conta) in &replay.contas {
+                write_record(
+                    &mut out,
+                    &WalRecord::Conta {
+                        cliente: *cliente,
+                        saldo: conta.saldo,
+                        historico: conta.historico.clone(),
+                    },
+                )?;
+            }
+            out.sync_all()?;
+        }
+        std::fs::rename(&tmp, &path).context("replace WAL")?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .context("open WAL")?;
+
+        info!(
+            "wal: replayed {} processed, {} pending, {} contas from {}",
+            replay.processed.len(),
+            replay.pending.len(),
+            replay.contas.len(),
+            path.display()
+        );
+
+        let wait_sync = matches!(policy, FsyncPolicy::Always);
+        let (tx, rx) = mpsc::channel();
+        let writer = std::thread::Builder::new()
+            .name("wal-writer".into())
+            .spawn(move || run_writer(file, &path, &policy, rx))
+            .context("spawn WAL writer")?;
+
+        Ok((
+            Self {
+                tx: Some(tx),
+                wait_sync,
+                writer: Some(writer),
+            },
+            replay,
+        ))
+    }
+
+    /// Lê o WAL existente e reconstrói o estado
+    /// Linhas corrompidas (ex: escrita interrompida por crash) são ignoradas
+    fn replay(path: &Path) -> anyhow::Result<Replay> {
+        let mut replay = Replay::default();
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(replay),
+            Err(e) => return Err(e).context("read WAL"),
+        };
+
+        // id -> (valor, requestedAt, ordem de chegada)
+        let mut pending: HashMap<String, (Money, String, usize)> = HashMap::new();
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.context("read WAL")?;
+            let rec: WalRecord = match serde_json::from_str(&line) {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("wal: skipping corrupt line {}: {e}", n + 1);
+                    continue;
+                }
+            };
+
+            match rec {
+                WalRecord::Accepted {
+                    id,
+                    amount,
+                    requested_at,
+                } => {
+                    pending.insert(id, (amount, requested_at, n));
+                }
+                WalRecord::Processed {
+                    id,
+                    processor,
+                    amount,
+                    requested_at,
+                } => {
+                    pending.remove(&id);
+                    replay.processed.push((processor, amount, requested_at));
+                }
+                WalRecord::Failed { id } => {
+                    pending.remove(&id);
+                }
+                WalRecord::Purge => replay.processed.clear(),
+                WalRecord::Transacao { cliente, transacao } => {
+                    let conta = replay.contas.entry(cliente).or_default();
+                    let Some(saldo) = conta
+                        .saldo
+                        .checked_add(transacao.tipo.delta(transacao.valor))
+                    else {
+                        warn!("wal: skipping transacao at line {} (saldo overflow)", n + 1);
+                        continue;
+                    };
+                    conta.saldo = saldo;
+                    conta.historico.insert(0, transacao);
+                    conta.historico.truncate(HISTORY_LEN);
+                }
+                WalRecord::Conta {
+                    cliente,
+                    saldo,
+                    historico,
+                } => {
+                    replay
+                        .contas
+                        .insert(cliente, ContaSnapshot { saldo, historico });
+                }
+            }
+        }
+
+        let mut pending: Vec<_> = pending.into_iter().collect();
+        pending.sort_by_key(|(_, (_, _, n))| *n);
+        replay.pending = pending
+            .into_iter()
+            .map(|(id, (amount, requested_at, _))| (id, amount, requested_at))
+            .collect();
+        Ok(replay)
+    }
+
+    /// Enfileira registro para a thread de escrita (não bloqueia)
+    /// A ordem no arquivo é a ordem das chamadas; a resposta ao cliente deve
+    /// esperar `Appended::synced`. Falhas são logadas e contadas, sem derrubar
+    /// a requisição
+    pub fn append(&self, rec: &WalRecord) -> Appended {
+        let (synced, ack) = if self.wait_sync {
+            let (tx, rx) = oneshot::channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        let sent = encode_record(rec)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                let tx = self.tx.as_ref().expect("WAL writer closed");
+                tx.send(Line { bytes, synced })
+                    .map_err(|_| "writer stopped".to_string())
+            });
+        if let Err(e) = sent {
+            error!("wal: append failed: {e}");
+            metrics::counter!("wal_errors").increment(1);
+            return Appended(None);
+        }
+        Appended(ack)
+    }
+}
+
+impl Drop for Wal {
+    /// Fecha a fila e espera a thread gravar (e sincronizar) o que restou
+    fn drop(&mut self) {
+        drop(self.tx.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Loop da thread de escrita
+/// Junta as linhas pendentes num lote, grava com um write e sincroniza
+/// conforme a política; termina quando todos os `Sender` são descartados
+fn run_writer(mut file: File, path: &Path, policy: &FsyncPolicy, rx: Receiver<Line>) {
+    let mut batch = Vec::with_capacity(MAX_BATCH_BYTES);
+    // Quem espera o fsync das linhas do lote
+    let mut waiting: Vec<oneshot::Sender<()>> = Vec::new();
+    let mut dirty = false;
+    let mut last_sync = Instant::now();
+
+    loop {
+        // No modo interval a espera termina a tempo do próximo fsync
+        let first = match policy {
+            FsyncPolicy::Interval(every) if dirty => {
+                match rx.recv_timeout(every.saturating_sub(last_sync.elapsed())) {
+                    Ok(line) => Some(line),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            _ => match rx.recv() {
+                Ok(line) => Some(line),
+                Err(_) => break,
+            },
+        };
+
+        if let Some(line) = first {
+            let mut next = Some(line);
+            while let Some(line) = next.take() {
+                batch.extend_from_slice(&line.bytes);
+                waiting.extend(line.synced);
+                if batch.len() < MAX_BATCH_BYTES {
+                    next = rx.try_recv().ok();
+                }
+            }
+            if let Err(e) = file.write_all(&batch) {
+                error!("wal: append to {} failed: {e}", path.display());
+                metrics::counter!("wal_errors").increment(1);
+                // Sem aviso: quem espera vê a falha
+                waiting.clear();
+            }
+            batch.clear();
+            dirty = true;
+        }
+
+        let due = match policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Interval(every) => last_sync.elapsed() >= *every,
+            FsyncPolicy::Never => false,
+        };
+        if dirty && due {
+            let synced = sync(&file, path);
+            for tx in waiting.drain(..) {
+                if synced {
+                    let _ = tx.send(());
+                }
+            }
+            dirty = false;
+            last_sync = Instant::now();
+        }
+    }
+
+    // Encerramento: o que foi gravado vai para o disco
+    if dirty && !matches!(policy, FsyncPolicy::Never) {
+        sync(&file, path);
+    }
+}
+
+/// fsync dos dados gravados
+/// # Returns
+/// * `true` se os dados chegaram ao disco
+fn sync(file: &File, path: &Path) -> bool {
+    match file.sync_data() {
+        Ok(()) => true,
+        Err(e) => {
+            error!("wal: fsync {} failed: {e}", path.display());
+            metrics::counter!("wal_errors").increment(1);
+            false
+        }
+    }
+}
+
+/// Serializa registro como uma linha JSON
+fn encode_record(rec: &WalRecord) -> serde_json::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(rec)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Grava registro como uma linha JSON (compactação)
+fn write_record(out: &mut impl Write, rec: &WalRecord) -> anyhow::Result<()> {
+    out.write_all(&encode_record(rec)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Tipo;
+
+    fn accepted(id: &str, cents: i64) -> WalRecord {
+        WalRecord::Accepted {
+            id: id.into(),
+            amount: Money::from_cents(cents),
+            requested_at: format!("2026-01-01T00:00:0{}.000Z", cents % 10),
+        }
+    }
+
+    #[tokio::test]
+    async fn write_and_replay_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p99.wal");
+        let path = path.to_str().unwrap();
+
+        let (wal, replay) = Wal::open(path, FsyncPolicy::Always).unwrap();
+        assert!(replay.processed.is_empty() && replay.pending.is_empty());
+
+        wal.append(&accepted("a", 1990)).synced().await;
+        wal.append(&accepted("b", 1)).synced().await;
+        wal.append(&accepted("c", 12345)).synced().await;
+        wal.append(&WalRecord::Processed {
+            id: "a".into(),
+            processor: UpstreamId::B,
+            amount: Money::from_cents(1990),
+            requested_at: "2026-01-01T00:00:00.000Z".into(),
+        })
+        .synced()
+        .await;
+        wal.append(&WalRecord::Failed { id: "c".into() })
+            .synced()
+            .await;
+        wal.append(&WalRecord::Transacao {
+            cliente: 1,
+            transacao: Transacao {
+                valor: 500,
+                tipo: Tipo::Debito,
+                descricao: "x".into(),
+                realizada_em: "2026-01-01T00:00:00.000Z".into(),
+            },
+        })
+        .synced()
+        .await;
+        drop(wal);
+
+        // Duas reaberturas: a segunda lê o arquivo já compactado
+        for _ in 0..2 {
+            let (_, replay) = Wal::open(path, FsyncPolicy::Never).unwrap();
+            assert_eq!(replay.processed.len(), 1);
+            let (processor, amount, _) = &replay.processed[0];
+            assert_eq!(*processor, UpstreamId::B);
+            assert_eq!(*amount, Money::from_cents(1990));
+
+            assert_eq!(replay.pending.len(), 1);
+            assert_eq!(replay.pending[0].0, "b");
+            assert_eq!(replay.pending[0].1, Money::from_cents(1));
+
+            assert_eq!(replay.contas.len(), 1);
+            assert_eq!(replay.contas[&1].saldo, -500);
+            assert_eq!(replay.contas[&1].historico[0].valor, 500);
+        }
+    }
+
+    fn transacao(valor: i64, tipo: Tipo) -> WalRecord {
+        WalRecord::Transacao {
+            cliente: 7,
+            transacao: Transacao {
+                valor,
+                tipo,
+                descricao: format!("t{valor}"),
+                realizada_em: "2026-01-01T00:00:00.000Z".into(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn compaction_snapshots_balances() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p99.wal");
+        let path = path.to_str().unwrap();
+
+        let (wal, _) = Wal::open(path, FsyncPolicy::Interval(Duration::from_secs(60))).unwrap();
+        for valor in 1..=100 {
+            wal.append(&transacao(valor, Tipo::Credito)).synced().await;
+        }
+        wal.append(&transacao(1000, Tipo::Debito)).synced().await;
+        drop(wal);
+
+        let (wal, replay) = Wal::open(path, FsyncPolicy::Never).unwrap();
+        let conta = &replay.contas[&7];
+        assert_eq!(conta.saldo, 5050 - 1000);
+        assert_eq!(conta.historico.len(), HISTORY_LEN);
+        assert_eq!(conta.historico[0].valor, 1000);
+        assert_eq!(conta.historico[1].valor, 100);
+
+        // Arquivo compactado: uma linha por conta
+        let compacted = std::fs::read_to_string(path).unwrap();
+        assert_eq!(compacted.lines().count(), 1);
+
+        // Transações depois do snapshot continuam a partir dele
+        wal.append(&transacao(50, Tipo::Credito)).synced().await;
+        drop(wal);
+        let (_, replay) = Wal::open(path, FsyncPolicy::Never).unwrap();
+        let conta = &replay.contas[&7];
+        assert_eq!(conta.saldo, 5050 - 1000 + 50);
+        assert_eq!(conta.historico.len(), HISTORY_LEN);
+        assert_eq!(conta.historico[0].valor, 50);
+    }
+
+    #[tokio::test]
+    async fn replay_skips_transacoes_that_overflow_saldo() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p99.wal");
+        let path = path.to_str().unwrap();
+
+        let (wal, _) = Wal::open(path, FsyncPolicy::Never).unwrap();
+        wal.append(&transacao(i64::MAX, Tipo::Credito))
+            .synced()
+            .await;
+        wal.append(&transacao(1, Tipo::Credito)).synced().await;
+        wal.append(&transacao(10, Tipo::Debito)).synced().await;
+        drop(wal);
+
+        let (_, replay) = Wal::open(path, FsyncPolicy::Never).unwrap();
+        let conta = &replay.contas[&7];
+        assert_eq!(conta.saldo, i64::MAX - 10);
+        assert_eq!(conta.historico.len(), 2);
+    }
+
+    #[test]
+    fn replay_skips_corrupt_lines_and_honors_purge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p99.wal");
+        std::fs::write(
+            &path,
+            concat!(
+                r#"{"t":"processed","id":"","processor":"A","amount":1.00,"requested_at":"x"}"#,
+                "\n",
+                r#"{"t":"purge"}"#,
+                "\n",
+                r#"{"t":"processed","id":"","processor":"A","amount":2.50,"requested_at":"y"}"#,
+                "\n",
+                r#"{"t":"processed","id":"","processor""#,
+                "\n",
+            ),
+        )
+        .unwrap();
+
+        let (_, replay) = Wal::open(path.to_str().unwrap(), FsyncPolicy::Never).unwrap();
+        assert_eq!(replay.processed.len(), 1);
+        assert_eq!(replay.processed[0].1, Money::from_cents(250));
+    }
+
+    #[tokio::test]
+    async fn always_acknowledges_after_the_record_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p99.wal");
+
+        let (wal, _) = Wal::open(path.to_str().unwrap(), FsyncPolicy::Always).unwrap();
+        for i in 0..50 {
+            wal.append(&accepted(&format!("p{i}"), i)).synced().await;
+            // Sem Drop: o registro já precisa estar no arquivo
+            let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+            assert_eq!(lines, i as usize + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_appends_share_a_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p99.wal");
+
+        let (wal, _) = Wal::open(path.to_str().unwrap(), FsyncPolicy::Always).unwrap();
+        let pending: Vec<_> = (0..200)
+            .map(|i| wal.append(&accepted(&format!("p{i}"), i)))
+            .collect();
+        futures::future::join_all(pending.into_iter().map(Appended::synced)).await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 200);
+    }
+}
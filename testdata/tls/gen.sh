@@ -0,0 +1,28 @@
+#!/bin/sh
+# Regenera os certificados usados nos testes de TLS (src/tls.rs)
+# CA privada, servidor (localhost e psp.internal) e cliente para mTLS
+set -eu
+cd "$(dirname "$0")"
+DAYS=36500
+
+openssl ecparam -name prime256v1 -genkey -noout -out ca.key
+openssl req -x509 -new -key ca.key -sha256 -days $DAYS -subj "/CN=p99 test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" \
+    -out ca.pem
+
+issue() {
+    name=$1 ext=$2
+    openssl ecparam -name prime256v1 -genkey -noout -out "$name.key.tmp"
+    openssl pkcs8 -topk8 -nocrypt -in "$name.key.tmp" -out "$name.key"
+    rm "$name.key.tmp"
+    openssl req -new -key "$name.key" -subj "/CN=$name" -out "$name.csr"
+    printf '%b' "$ext" > "$name.ext"
+    openssl x509 -req -in "$name.csr" -CA ca.pem -CAkey ca.key -CAcreateserial \
+        -sha256 -days $DAYS -extfile "$name.ext" -out "$name.pem"
+    rm "$name.csr" "$name.ext"
+}
+
+issue server "basicConstraints=CA:FALSE\nkeyUsage=critical,digitalSignature\nextendedKeyUsage=serverAuth\nsubjectAltName=DNS:localhost,DNS:psp.internal\n"
+issue client "basicConstraints=CA:FALSE\nkeyUsage=critical,digitalSignature\nextendedKeyUsage=clientAuth\n"
+rm -f ca.srl ca.key